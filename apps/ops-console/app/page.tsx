@@ -591,10 +591,8 @@ export default function OpsConsole() {
     try {
       const messageId = window.prompt('DVN Message ID to attest:');
       if (!messageId) throw new Error('Message ID required');
-      const validatorsCsv = window.prompt('Validators (comma-separated) or leave blank for defaults:', '');
-      const validators = validatorsCsv ? validatorsCsv.split(',').map(s => s.trim()).filter(Boolean) : undefined;
-      await attestDVNMessage(messageId, validators);
-      setTestResults(prev => [...prev, { type: 'icp_attest_dvn', timestamp: new Date().toISOString(), data: { messageId, validators }, status: 'success' }]);
+      await attestDVNMessage(messageId);
+      setTestResults(prev => [...prev, { type: 'icp_attest_dvn', timestamp: new Date().toISOString(), data: { messageId }, status: 'success' }]);
     } catch (e: any) {
       setTestResults(prev => [...prev, { type: 'icp_attest_dvn_error', timestamp: new Date().toISOString(), error: e?.message || 'attestation failed', status: 'error' }]);
     }
@@ -1,10 +1,14 @@
 use candid::{CandidType, Deserialize};
-use ic_cdk::{query, update, api::management_canister::{
+use ic_cdk::{init, post_upgrade, query, update, api::management_canister::{
     ecdsa::{ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument},
-    http_request::{http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformContext, TransformFunc}
+    http_request::{http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs, TransformContext, TransformFunc}
 }};
+use ic_stable_structures::{StableBTreeMap, StableCell};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+
+mod storage;
+
+use storage::Memory;
 
 #[derive(CandidType, Deserialize, Clone)]
 pub struct BitcoinAddress {
@@ -48,13 +52,98 @@ pub struct SignedTransaction {
     pub fee: u64,
 }
 
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BtcNetwork {
+    Mainnet,
+    #[default]
+    Testnet,
+    Regtest,
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct InitArgs {
+    pub network: Option<BtcNetwork>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransactionStatus {
+    pub confirmed: bool,
+    pub block_height: Option<u64>,
+    pub confirmations: u32,
+}
+
+// Block-inclusion (SPV) proof for a confirmed transaction. Hashes are in
+// Bitcoin display order, as Esplora returns them.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AnchorProof {
+    pub txid: String,
+    pub raw_tx: String,
+    pub block_height: u64,
+    pub block_hash: String,
+    pub block_header: String,
+    pub merkle_path: Vec<String>,
+    pub tx_index: u32,
+}
+
 thread_local! {
-    static ADDRESSES: std::cell::RefCell<HashMap<String, BitcoinAddress>> = std::cell::RefCell::new(HashMap::new());
-    static TRANSACTIONS: std::cell::RefCell<HashMap<String, SignedTransaction>> = std::cell::RefCell::new(HashMap::new());
+    static ADDRESSES: std::cell::RefCell<StableBTreeMap<String, BitcoinAddress, Memory>> =
+        std::cell::RefCell::new(StableBTreeMap::init(storage::memory(storage::ADDRESSES_MEMORY)));
+    static TRANSACTIONS: std::cell::RefCell<StableBTreeMap<String, SignedTransaction, Memory>> =
+        std::cell::RefCell::new(StableBTreeMap::init(storage::memory(storage::TRANSACTIONS_MEMORY)));
+    static NETWORK: std::cell::RefCell<StableCell<BtcNetwork, Memory>> = std::cell::RefCell::new(
+        StableCell::init(storage::memory(storage::NETWORK_MEMORY), BtcNetwork::default())
+            .expect("failed to init network cell")
+    );
 }
 
 const KEY_NAME: &str = "test_key_1";
 
+#[init]
+fn init(args: Option<InitArgs>) {
+    storage::init_schema();
+    apply_init_args(args.unwrap_or_default());
+}
+
+#[post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    storage::migrate();
+    if let Some(args) = args {
+        apply_init_args(args);
+    }
+}
+
+fn apply_init_args(args: InitArgs) {
+    if let Some(network) = args.network {
+        NETWORK.with(|n| n.borrow_mut().set(network)).expect("failed to write network");
+    }
+}
+
+#[query]
+pub fn get_network() -> BtcNetwork {
+    NETWORK.with(|n| *n.borrow().get())
+}
+
+// Esplora instance for the configured network; regtest has no public one
+fn esplora_api() -> Result<&'static str, String> {
+    match get_network() {
+        BtcNetwork::Mainnet => Ok("https://blockstream.info/api"),
+        BtcNetwork::Testnet => Ok("https://blockstream.info/testnet/api"),
+        BtcNetwork::Regtest => Err("No Esplora API is available for regtest".to_string()),
+    }
+}
+
+// Every outcall goes through `transform_response`, so replicas compare only
+// what they all agree on
+fn transform() -> TransformContext {
+    TransformContext {
+        function: TransformFunc(candid::Func {
+            principal: ic_cdk::api::id(),
+            method: "transform_response".to_string(),
+        }),
+        context: vec![],
+    }
+}
+
 #[update]
 pub async fn get_btc_address(derivation_path: Vec<Vec<u8>>) -> Result<BitcoinAddress, String> {
     let key_id = EcdsaKeyId {
@@ -182,21 +271,15 @@ pub async fn sign_transaction(
 
 #[update]
 pub async fn broadcast_transaction(raw_tx: String) -> Result<String, String> {
-    // Broadcast to Bitcoin testnet via HTTP outcalls
+    // Broadcast to the configured network via HTTP outcalls
     let request_body = format!(r#"{{"jsonrpc":"1.0","id":"broadcast","method":"sendrawtransaction","params":["{}"]}}"#, raw_tx);
     
     let request = CanisterHttpRequestArgument {
-        url: "https://blockstream.info/testnet/api/tx".to_string(),
+        url: format!("{}/tx", esplora_api()?),
         method: HttpMethod::POST,
         body: Some(request_body.into_bytes()),
         max_response_bytes: Some(1024),
-        transform: Some(TransformContext {
-            function: TransformFunc(candid::Func {
-                principal: ic_cdk::api::id(),
-                method: "transform_response".to_string(),
-            }),
-            context: vec![],
-        }),
+        transform: Some(transform()),
         headers: vec![
             HttpHeader {
                 name: "Content-Type".to_string(),
@@ -268,28 +351,115 @@ pub async fn create_and_broadcast_anchor(data_hash: String, fee_rate: u64) -> Re
     broadcast_transaction(signed_tx.raw_tx).await
 }
 
+// The tip can move between replicas' fetches; such a call fails consensus and
+// the caller's next poll retries it
+#[update]
+pub async fn get_transaction_status(txid: String) -> Result<TransactionStatus, String> {
+    let api = esplora_api()?;
+    let status_body = esplora_get(format!("{}/tx/{}/status", api, txid), 1024).await?;
+    let tip_body = esplora_get(format!("{}/blocks/tip/height", api), 1024).await?;
+    parse_transaction_status(&status_body, &tip_body)
+}
+
+#[update]
+pub async fn get_anchor_proof(txid: String) -> Result<AnchorProof, String> {
+    let api = esplora_api()?;
+    let raw_tx = esplora_get(format!("{}/tx/{}/hex", api, txid), 16_384).await?;
+    let merkle_body = esplora_get(format!("{}/tx/{}/merkle-proof", api, txid), 4096).await?;
+    let (block_height, merkle_path, tx_index) = parse_merkle_proof(&merkle_body)?;
+    let block_hash = esplora_get(format!("{}/block-height/{}", api, block_height), 1024).await?;
+    let block_header = esplora_get(format!("{}/block/{}/header", api, block_hash.trim()), 1024).await?;
+
+    Ok(AnchorProof {
+        txid,
+        raw_tx: raw_tx.trim().to_string(),
+        block_height,
+        block_hash: block_hash.trim().to_string(),
+        block_header: block_header.trim().to_string(),
+        merkle_path,
+        tx_index,
+    })
+}
+
+async fn esplora_get(url: String, max_response_bytes: u64) -> Result<String, String> {
+    let request = CanisterHttpRequestArgument {
+        url,
+        method: HttpMethod::GET,
+        body: None,
+        max_response_bytes: Some(max_response_bytes),
+        transform: Some(transform()),
+        headers: vec![],
+    };
+
+    match http_request(request, 2_000_000_000u128).await {
+        Ok((response,)) => {
+            if response.status == 200u8 {
+                Ok(String::from_utf8_lossy(&response.body).to_string())
+            } else {
+                Err(format!("Esplora request failed with status: {}", response.status))
+            }
+        }
+        Err(e) => Err(format!("HTTP request failed: {:?}", e)),
+    }
+}
+
+fn parse_transaction_status(status_body: &str, tip_body: &str) -> Result<TransactionStatus, String> {
+    let status: serde_json::Value = serde_json::from_str(status_body)
+        .map_err(|e| format!("JSON parse error: {}", e))?;
+    let confirmed = status.get("confirmed").and_then(|v| v.as_bool()).unwrap_or(false);
+    let block_height = status.get("block_height").and_then(|v| v.as_u64());
+
+    let confirmations = match (confirmed, block_height) {
+        (true, Some(height)) => {
+            let tip: u64 = tip_body.trim().parse()
+                .map_err(|e| format!("Invalid tip height: {}", e))?;
+            (tip.saturating_sub(height) + 1) as u32
+        }
+        _ => 0,
+    };
+
+    Ok(TransactionStatus { confirmed, block_height, confirmations })
+}
+
+fn parse_merkle_proof(body: &str) -> Result<(u64, Vec<String>, u32), String> {
+    let proof: serde_json::Value = serde_json::from_str(body)
+        .map_err(|e| format!("JSON parse error: {}", e))?;
+    let block_height = proof.get("block_height").and_then(|v| v.as_u64())
+        .ok_or("Merkle proof missing block_height")?;
+    let tx_index = proof.get("pos").and_then(|v| v.as_u64())
+        .ok_or("Merkle proof missing pos")? as u32;
+    let merkle_path = proof.get("merkle").and_then(|v| v.as_array())
+        .ok_or("Merkle proof missing merkle path")?
+        .iter()
+        .map(|h| h.as_str().map(str::to_string).ok_or("Merkle path entries must be strings"))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((block_height, merkle_path, tx_index))
+}
+
+// Headers (dates, request IDs, caching) differ between replicas; status and
+// body are what callers read
 #[query]
-pub fn transform_response(args: TransformContext) -> HttpResponse {
+pub fn transform_response(args: TransformArgs) -> HttpResponse {
     HttpResponse {
-        status: 200u8.into(),
+        status: args.response.status,
         headers: vec![],
-        body: args.context,
+        body: args.response.body,
     }
 }
 
 #[query]
 pub fn get_transaction(txid: String) -> Option<SignedTransaction> {
-    TRANSACTIONS.with(|t| t.borrow().get(&txid).cloned())
+    TRANSACTIONS.with(|t| t.borrow().get(&txid))
 }
 
 #[query]
 pub fn get_address_info(address: String) -> Option<BitcoinAddress> {
-    ADDRESSES.with(|a| a.borrow().get(&address).cloned())
+    ADDRESSES.with(|a| a.borrow().get(&address))
 }
 
 #[query]
 pub fn get_all_addresses() -> Vec<BitcoinAddress> {
-    ADDRESSES.with(|a| a.borrow().values().cloned().collect())
+    ADDRESSES.with(|a| a.borrow().iter().map(|(_, address)| address).collect())
 }
 
 // Export Candid interface
@@ -299,11 +469,50 @@ ic_cdk::export_candid!();
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_confirmed_status() {
+        let status = parse_transaction_status(
+            r#"{"confirmed":true,"block_height":100,"block_hash":"00ab","block_time":1}"#,
+            "105\n",
+        ).unwrap();
+        assert_eq!(status, TransactionStatus { confirmed: true, block_height: Some(100), confirmations: 6 });
+
+        let pending = parse_transaction_status(r#"{"confirmed":false}"#, "").unwrap();
+        assert_eq!(pending.confirmations, 0);
+    }
+
+    #[test]
+    fn parse_esplora_merkle_proof() {
+        let (height, path, pos) = parse_merkle_proof(r#"{"block_height":100,"merkle":["aa","bb"],"pos":3}"#).unwrap();
+        assert_eq!((height, path, pos), (100, vec!["aa".to_string(), "bb".to_string()], 3));
+        assert!(parse_merkle_proof(r#"{"block_height":100}"#).is_err());
+    }
+
+    #[test]
+    fn esplora_follows_the_configured_network() {
+        assert_eq!(esplora_api(), Ok("https://blockstream.info/testnet/api"));
+        apply_init_args(InitArgs { network: Some(BtcNetwork::Mainnet) });
+        assert_eq!(esplora_api(), Ok("https://blockstream.info/api"));
+        apply_init_args(InitArgs::default());
+        assert_eq!(get_network(), BtcNetwork::Mainnet);
+        apply_init_args(InitArgs { network: Some(BtcNetwork::Regtest) });
+        assert!(esplora_api().is_err());
+
+        let response = HttpResponse {
+            status: 200u8.into(),
+            headers: vec![HttpHeader { name: "Date".to_string(), value: "now".to_string() }],
+            body: b"105".to_vec(),
+        };
+        let transformed = transform_response(TransformArgs { response, context: vec![] });
+        assert!(transformed.headers.is_empty());
+        assert_eq!(transformed.body, b"105".to_vec());
+    }
+
     #[test]
     fn anchor_tx_requires_utxos() {
         let res = futures::executor::block_on(create_anchor_transaction(
-            "abcd".to_string(),
             vec![],
+            "abcd".to_string(),
             10,
         ));
         assert!(res.is_err());
@@ -318,8 +527,8 @@ mod tests {
             script_pubkey: vec![],
         };
         let res = futures::executor::block_on(create_anchor_transaction(
-            "deadbeef".to_string(),
             vec![utxo],
+            "deadbeef".to_string(),
             10,
         ));
         let tx = res.expect("should build unsigned tx");
@@ -0,0 +1,105 @@
+//! Stable-memory plumbing shared by the canisters.
+//!
+//! Every piece of canister state lives in its own virtual memory handed out by
+//! a single `MemoryManager`, so it survives `dfx deploy --mode upgrade` without
+//! a `pre_upgrade` serialization pass. Memory 0 holds the schema version; each
+//! canister numbers its own memories from 1 and keeps its migration steps.
+//! Records are stored as Candid, which lets new `opt` fields be added without
+//! rewriting existing entries; layout changes that Candid cannot absorb bump
+//! the canister's schema version and get a step in its `migrate`.
+
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::memory_manager::{MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableCell};
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+
+pub use ic_stable_structures;
+pub use ic_stable_structures::memory_manager::MemoryId;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Part of every canister's on-chain layout: never reuse or renumber it.
+pub const SCHEMA_MEMORY: MemoryId = MemoryId::new(0);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // 0 means "no schema recorded yet", i.e. a fresh install or a canister
+    // upgraded from the heap-only layout.
+    static SCHEMA: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(memory(SCHEMA_MEMORY), 0).expect("failed to init schema cell")
+    );
+}
+
+pub fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+pub fn schema_version() -> u32 {
+    SCHEMA.with(|s| *s.borrow().get())
+}
+
+pub fn set_schema_version(version: u32) {
+    SCHEMA.with(|s| s.borrow_mut().set(version).expect("failed to write schema version"));
+}
+
+/// Runs `step(from)` for every schema version from the stored one up to
+/// `current`, then records `current`. Refuses stable memory written by a newer
+/// build; the caller traps on the error.
+pub fn migrate(current: u32, mut step: impl FnMut(u32)) -> Result<(), String> {
+    let stored = schema_version();
+    if stored > current {
+        return Err(format!("Stable memory schema v{} is newer than this build (v{})", stored, current));
+    }
+    for from in stored..current {
+        step(from);
+    }
+    set_schema_version(current);
+    Ok(())
+}
+
+pub fn encode<T: CandidType>(value: &T) -> Vec<u8> {
+    Encode!(value).expect("failed to encode stable record")
+}
+
+pub fn decode<T: CandidType + DeserializeOwned>(bytes: &[u8]) -> T {
+    Decode!(bytes, T).expect("failed to decode stable record")
+}
+
+/// Implements `Storable` for each listed type by Candid-encoding it.
+#[macro_export]
+macro_rules! candid_storable {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl $crate::ic_stable_structures::Storable for $ty {
+                fn to_bytes(&self) -> ::std::borrow::Cow<'_, [u8]> {
+                    ::std::borrow::Cow::Owned($crate::encode(self))
+                }
+
+                fn from_bytes(bytes: ::std::borrow::Cow<[u8]>) -> Self {
+                    $crate::decode(bytes.as_ref())
+                }
+
+                const BOUND: $crate::ic_stable_structures::storable::Bound =
+                    $crate::ic_stable_structures::storable::Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_runs_each_step_once_and_refuses_newer_schemas() {
+        let mut steps = Vec::new();
+        migrate(3, |from| steps.push(from)).unwrap();
+        assert_eq!((steps, schema_version()), (vec![0, 1, 2], 3));
+
+        migrate(3, |_| panic!("already current")).unwrap();
+        assert!(migrate(2, |_| {}).unwrap_err().contains("newer than this build"));
+    }
+}
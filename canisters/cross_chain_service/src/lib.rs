@@ -1,11 +1,15 @@
-use candid::{CandidType, Deserialize};
-use ic_cdk::{query, update, api::management_canister::http_request::{
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::{init, post_upgrade, query, update, api::management_canister::http_request::{
     http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
 }};
 use ic_cdk_timers::{set_timer, TimerId};
-use std::collections::HashMap;
+use ic_stable_structures::StableBTreeMap;
 use std::time::Duration;
 
+mod storage;
+
+use storage::Memory;
+
 #[derive(CandidType, Deserialize, Clone)]
 pub struct DVNMessage {
     pub id: String,
@@ -25,6 +29,10 @@ pub struct DVNAttestation {
     pub timestamp: u64,
 }
 
+// Stable-map value wrapper for a message's attestations
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct AttestationList(pub Vec<DVNAttestation>);
+
 #[derive(CandidType, Deserialize, Clone)]
 pub struct CrossChainTransaction {
     pub id: String,
@@ -38,9 +46,15 @@ pub struct CrossChainTransaction {
 }
 
 thread_local! {
-    static DVN_MESSAGES: std::cell::RefCell<HashMap<String, DVNMessage>> = std::cell::RefCell::new(HashMap::new());
-    static ATTESTATIONS: std::cell::RefCell<HashMap<String, Vec<DVNAttestation>>> = std::cell::RefCell::new(HashMap::new());
-    static TRANSACTIONS: std::cell::RefCell<HashMap<String, CrossChainTransaction>> = std::cell::RefCell::new(HashMap::new());
+    static DVN_MESSAGES: std::cell::RefCell<StableBTreeMap<String, DVNMessage, Memory>> =
+        std::cell::RefCell::new(StableBTreeMap::init(storage::memory(storage::DVN_MESSAGES_MEMORY)));
+    static ATTESTATIONS: std::cell::RefCell<StableBTreeMap<String, AttestationList, Memory>> =
+        std::cell::RefCell::new(StableBTreeMap::init(storage::memory(storage::ATTESTATIONS_MEMORY)));
+    static TRANSACTIONS: std::cell::RefCell<StableBTreeMap<String, CrossChainTransaction, Memory>> =
+        std::cell::RefCell::new(StableBTreeMap::init(storage::memory(storage::TRANSACTIONS_MEMORY)));
+    // Principals whose attestations count towards quorum; managed by controllers
+    static VALIDATORS: std::cell::RefCell<StableBTreeMap<Principal, (), Memory>> =
+        std::cell::RefCell::new(StableBTreeMap::init(storage::memory(storage::VALIDATORS_MEMORY)));
     static TIMER_IDS: std::cell::RefCell<Vec<TimerId>> = std::cell::RefCell::new(Vec::new());
 }
 
@@ -62,6 +76,69 @@ fn now_millis() -> u64 {
     }
 }
 
+// Host-friendly caller helpers: host tests run as the anonymous principal with
+// controller rights
+fn caller() -> Principal {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::caller()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Principal::anonymous()
+    }
+}
+
+fn require_controller() -> Result<(), String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+            return Err("Caller is not a controller".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn is_validator(principal: &Principal) -> bool {
+    VALIDATORS.with(|v| v.borrow().contains_key(principal))
+}
+
+// Registered validators that attested the message, each counted once
+fn attesting_validators(message_id: &str) -> std::collections::BTreeSet<Principal> {
+    ATTESTATIONS.with(|a| {
+        a.borrow()
+            .get(&message_id.to_string())
+            .map(|list| list.0)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|att| Principal::from_text(&att.validator).ok())
+            .filter(is_validator)
+            .collect()
+    })
+}
+
+fn quorum_reached(message_id: &str) -> bool {
+    attesting_validators(message_id).len() >= REQUIRED_ATTESTATIONS
+}
+
+#[init]
+fn init() {
+    storage::init_schema();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    storage::migrate();
+    
+    // Timers do not survive an upgrade; resume monitoring for messages still short of quorum
+    for message in get_pending_messages() {
+        let timer_id = set_timer(Duration::from_secs(30), move || {
+            ic_cdk::spawn(check_message_attestations(message.id.clone()));
+        });
+        TIMER_IDS.with(|t| t.borrow_mut().push(timer_id));
+    }
+}
+
 #[update]
 pub fn submit_dvn_message(
     source_chain: u32,
@@ -96,11 +173,39 @@ pub fn submit_dvn_message(
 }
 
 #[update]
-pub fn submit_attestation(
-    message_id: String,
-    validator: String,
-    signature: Vec<u8>,
-) -> Result<String, String> {
+pub fn add_validator(validator: Principal) -> Result<(), String> {
+    require_controller()?;
+    if validator == Principal::anonymous() {
+        return Err("The anonymous principal cannot be a validator".to_string());
+    }
+    VALIDATORS.with(|v| v.borrow_mut().insert(validator, ()));
+    Ok(())
+}
+
+// Attestations already made by a removed validator stop counting towards quorum
+#[update]
+pub fn remove_validator(validator: Principal) -> Result<(), String> {
+    require_controller()?;
+    VALIDATORS
+        .with(|v| v.borrow_mut().remove(&validator))
+        .ok_or_else(|| format!("{} is not a validator", validator))
+}
+
+#[query]
+pub fn get_validators() -> Vec<Principal> {
+    VALIDATORS.with(|v| v.borrow().iter().map(|(validator, _)| validator).collect())
+}
+
+// Records an attestation from the calling validator
+#[update]
+pub fn submit_attestation(message_id: String, signature: Vec<u8>) -> Result<String, String> {
+    attest(caller(), message_id, signature)
+}
+
+fn attest(validator: Principal, message_id: String, signature: Vec<u8>) -> Result<String, String> {
+    if !is_validator(&validator) {
+        return Err(format!("{} is not a registered validator", validator));
+    }
     let message_exists = DVN_MESSAGES.with(|m| m.borrow().contains_key(&message_id));
     
     if !message_exists {
@@ -109,20 +214,20 @@ pub fn submit_attestation(
     
     let attestation = DVNAttestation {
         message_id: message_id.clone(),
-        validator,
+        validator: validator.to_text(),
         signature,
         timestamp: now_millis(),
     };
     
     ATTESTATIONS.with(|a| {
         let mut attestations = a.borrow_mut();
-        attestations.entry(message_id.clone()).or_insert_with(Vec::new).push(attestation);
+        let mut list = attestations.get(&message_id).unwrap_or_default();
+        list.0.push(attestation);
+        attestations.insert(message_id.clone(), list);
     });
     
     // Check if we have enough attestations
-    let attestation_count = ATTESTATIONS.with(|a| {
-        a.borrow().get(&message_id).map(|v| v.len()).unwrap_or(0)
-    });
+    let attestation_count = attesting_validators(&message_id).len();
     
     if attestation_count >= REQUIRED_ATTESTATIONS {
         Ok(format!("Message {} ready for execution with {} attestations", message_id, attestation_count))
@@ -185,11 +290,7 @@ pub async fn monitor_evm_transaction(
 }
 
 async fn check_message_attestations(message_id: String) {
-    let attestation_count = ATTESTATIONS.with(|a| {
-        a.borrow().get(&message_id).map(|v| v.len()).unwrap_or(0)
-    });
-    
-    if attestation_count >= REQUIRED_ATTESTATIONS {
+    if quorum_reached(&message_id) {
         // Message is ready for cross-chain execution
         ic_cdk::println!("Message {} ready for execution", message_id);
     } else {
@@ -229,42 +330,43 @@ pub async fn verify_layerzero_message(
 
 #[query]
 pub fn get_dvn_message(message_id: String) -> Option<DVNMessage> {
-    DVN_MESSAGES.with(|m| m.borrow().get(&message_id).cloned())
+    DVN_MESSAGES.with(|m| m.borrow().get(&message_id))
 }
 
 #[query]
 pub fn get_message_attestations(message_id: String) -> Vec<DVNAttestation> {
     ATTESTATIONS.with(|a| {
-        a.borrow().get(&message_id).cloned().unwrap_or_default()
+        a.borrow().get(&message_id).map(|list| list.0).unwrap_or_default()
     })
 }
 
+// Whether the message exists and has attestations from at least
+// REQUIRED_ATTESTATIONS distinct registered validators; used by proof_of_state
+// before a burn
+#[query]
+pub fn is_quorum_reached(message_id: String) -> Result<bool, String> {
+    if !DVN_MESSAGES.with(|m| m.borrow().contains_key(&message_id)) {
+        return Err("Message not found".to_string());
+    }
+    Ok(quorum_reached(&message_id))
+}
+
 #[query]
 pub fn get_transaction(tx_id: String) -> Option<CrossChainTransaction> {
-    TRANSACTIONS.with(|t| t.borrow().get(&tx_id).cloned())
+    TRANSACTIONS.with(|t| t.borrow().get(&tx_id))
 }
 
 #[query]
 pub fn get_pending_messages() -> Vec<DVNMessage> {
     DVN_MESSAGES.with(|m| {
-        m.borrow().values().filter(|msg| {
-            let attestation_count = ATTESTATIONS.with(|a| {
-                a.borrow().get(&msg.id).map(|v| v.len()).unwrap_or(0)
-            });
-            attestation_count < REQUIRED_ATTESTATIONS
-        }).cloned().collect()
+        m.borrow().iter().map(|(_, msg)| msg).filter(|msg| !quorum_reached(&msg.id)).collect()
     })
 }
 
 #[query]
 pub fn get_ready_messages() -> Vec<DVNMessage> {
     DVN_MESSAGES.with(|m| {
-        m.borrow().values().filter(|msg| {
-            let attestation_count = ATTESTATIONS.with(|a| {
-                a.borrow().get(&msg.id).map(|v| v.len()).unwrap_or(0)
-            });
-            attestation_count >= REQUIRED_ATTESTATIONS
-        }).cloned().collect()
+        m.borrow().iter().map(|(_, msg)| msg).filter(|msg| quorum_reached(&msg.id)).collect()
     })
 }
 
@@ -275,19 +377,48 @@ ic_cdk::export_candid!();
 mod tests {
     use super::*;
 
+    fn validator(id: u8) -> Principal {
+        let validator = Principal::from_slice(&[id; 29]);
+        add_validator(validator).unwrap();
+        validator
+    }
+
     #[test]
     fn submit_message_and_attest() {
         let msg_id = submit_dvn_message(1, 2, vec![1,2,3], "sender".to_string());
         assert!(msg_id.starts_with("msg_"));
 
         // First attestation
-        let r1 = submit_attestation(msg_id.clone(), "v1".to_string(), vec![0x01]);
+        let r1 = attest(validator(1), msg_id.clone(), vec![0x01]);
         assert!(r1.is_ok());
         let text1 = r1.unwrap();
         assert!(text1.contains("Attestation recorded") || text1.contains("ready for execution"));
 
         // Second attestation should reach quorum
-        let r2 = submit_attestation(msg_id.clone(), "v2".to_string(), vec![0x02]).unwrap();
+        let r2 = attest(validator(2), msg_id.clone(), vec![0x02]).unwrap();
         assert!(r2.contains("ready for execution") || r2.contains("Attestation recorded"));
     }
+
+    #[test]
+    fn quorum_needs_distinct_registered_validators() {
+        let msg_id = submit_dvn_message(1, 2, vec![4], "sender".to_string());
+        assert!(is_quorum_reached("msg_missing".to_string()).is_err());
+        let (v1, v2) = (validator(1), validator(2));
+        attest(v1, msg_id.clone(), vec![0x01]).unwrap();
+        attest(v1, msg_id.clone(), vec![0x01]).unwrap();
+        assert_eq!(is_quorum_reached(msg_id.clone()), Ok(false));
+        // Unregistered callers cannot attest, whatever they claim to be
+        assert!(attest(Principal::from_slice(&[9; 29]), msg_id.clone(), vec![0x09]).is_err());
+        assert!(submit_attestation(msg_id.clone(), vec![0x09]).is_err());
+        assert_eq!(is_quorum_reached(msg_id.clone()), Ok(false));
+
+        attest(v2, msg_id.clone(), vec![0x02]).unwrap();
+        assert_eq!(is_quorum_reached(msg_id.clone()), Ok(true));
+        assert_eq!(get_ready_messages().len(), 1);
+        // Removing a validator withdraws its attestations
+        remove_validator(v2).unwrap();
+        assert_eq!(is_quorum_reached(msg_id), Ok(false));
+        assert_eq!(get_pending_messages().len(), 1);
+        assert!(add_validator(Principal::anonymous()).is_err());
+    }
 }
@@ -0,0 +1,36 @@
+//! Stable-memory layout for cross_chain_service.
+//!
+//! DVN messages, attestations, registered validators and tracked transactions
+//! live in stable memory so
+//! they survive `dfx deploy --mode upgrade`. Timers cannot be persisted and are
+//! re-armed from the stored messages in `post_upgrade`. The memory manager and
+//! Candid encoding come from `canister_storage`.
+
+use canister_storage::MemoryId;
+
+pub use canister_storage::{memory, Memory};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Memory IDs are part of the on-chain layout: never reuse or renumber them.
+// Memory 0 holds the schema version (`canister_storage::SCHEMA_MEMORY`).
+pub const DVN_MESSAGES_MEMORY: MemoryId = MemoryId::new(1);
+pub const ATTESTATIONS_MEMORY: MemoryId = MemoryId::new(2);
+pub const TRANSACTIONS_MEMORY: MemoryId = MemoryId::new(3);
+pub const VALIDATORS_MEMORY: MemoryId = MemoryId::new(4);
+
+pub fn init_schema() {
+    canister_storage::set_schema_version(SCHEMA_VERSION);
+}
+
+pub fn migrate() {
+    let migrated = canister_storage::migrate(SCHEMA_VERSION, |from| match from {
+        // Heap-only state from before stable storage is gone after the
+        // upgrade, so there is nothing to carry over.
+        0 => {}
+        _ => unreachable!("no migration from schema v{}", from),
+    });
+    migrated.unwrap_or_else(|e| ic_cdk::trap(&e));
+}
+
+canister_storage::candid_storable!(crate::DVNMessage, crate::AttestationList, crate::CrossChainTransaction);
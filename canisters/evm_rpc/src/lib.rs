@@ -1,10 +1,15 @@
 use candid::{CandidType, Deserialize};
-use ic_cdk::{query, update, api::management_canister::http_request::{
+use ic_cdk::{init, post_upgrade, query, update, api::management_canister::http_request::{
     http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
 }};
+use ic_stable_structures::StableBTreeMap;
 use serde_json::{Value, json};
 use std::collections::HashMap;
 
+mod storage;
+
+use storage::Memory;
+
 #[derive(CandidType, Deserialize, Clone)]
 pub struct EVMChainConfig {
     pub chain_id: u32,
@@ -47,11 +52,22 @@ pub struct BlockInfo {
 }
 
 thread_local! {
-    static CHAIN_CONFIGS: std::cell::RefCell<HashMap<u32, EVMChainConfig>> = std::cell::RefCell::new(HashMap::new());
+    static CHAIN_CONFIGS: std::cell::RefCell<StableBTreeMap<u32, EVMChainConfig, Memory>> =
+        std::cell::RefCell::new(StableBTreeMap::init(storage::memory(storage::CHAIN_CONFIGS_MEMORY)));
     static CACHED_RECEIPTS: std::cell::RefCell<HashMap<String, TransactionReceipt>> = std::cell::RefCell::new(HashMap::new());
     static CACHED_BLOCKS: std::cell::RefCell<HashMap<u64, BlockInfo>> = std::cell::RefCell::new(HashMap::new());
 }
 
+#[init]
+fn init() {
+    storage::init_schema();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    storage::migrate();
+}
+
 #[update]
 pub fn init_chain_configs() {
     let configs = vec![
@@ -309,12 +325,12 @@ fn parse_hex_to_u32(hex_str: &str) -> Result<u32, String> {
 
 #[query]
 pub fn get_chain_config(chain_id: u32) -> Option<EVMChainConfig> {
-    CHAIN_CONFIGS.with(|c| c.borrow().get(&chain_id).cloned())
+    CHAIN_CONFIGS.with(|c| c.borrow().get(&chain_id))
 }
 
 #[query]
 pub fn get_supported_chains() -> Vec<EVMChainConfig> {
-    CHAIN_CONFIGS.with(|c| c.borrow().values().cloned().collect())
+    CHAIN_CONFIGS.with(|c| c.borrow().iter().map(|(_, config)| config).collect())
 }
 
 #[query]
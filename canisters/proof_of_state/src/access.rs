@@ -0,0 +1,187 @@
+//! Role-based access control for mutating endpoints.
+//!
+//! `Issuer` may issue receipts and revoke or supersede its own, `Burner` may
+//! change burn state, `Subscriber` may register for push notifications and
+//! `Admin` may cut and anchor batches, tune the scheduler and config, and
+//! revoke or supersede any receipt. Roles are granted and revoked by canister
+//! controllers only; controllers implicitly hold every role so a fresh install
+//! is never locked out.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::storage::{self, Memory};
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Admin,
+    Issuer,
+    Burner,
+    Subscriber,
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct RoleSet(pub Vec<Role>);
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RoleGrant {
+    pub principal: Principal,
+    pub roles: Vec<Role>,
+}
+
+thread_local! {
+    static ROLES: RefCell<StableBTreeMap<Principal, RoleSet, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ROLES_MEMORY)));
+}
+
+// Host-friendly caller helpers: host tests run as the anonymous principal with
+// controller rights, like a locally deployed canister called by its deployer.
+pub fn caller() -> Principal {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::caller()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Principal::anonymous()
+    }
+}
+
+fn is_controller(principal: &Principal) -> bool {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::is_controller(principal)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = principal;
+        true
+    }
+}
+
+pub fn roles_of(principal: &Principal) -> Vec<Role> {
+    ROLES.with(|r| r.borrow().get(principal).map(|set| set.0).unwrap_or_default())
+}
+
+fn check(principal: &Principal, role: Role, is_controller: bool) -> Result<(), String> {
+    if is_controller || roles_of(principal).contains(&role) {
+        Ok(())
+    } else {
+        Err(format!("Caller {} lacks the {:?} role", principal, role))
+    }
+}
+
+/// Checks the caller holds `role` and returns it for recording.
+pub fn require(role: Role) -> Result<Principal, String> {
+    let caller = caller();
+    check(&caller, role, is_controller(&caller))?;
+    Ok(caller)
+}
+
+fn check_role_or_admin(principal: &Principal, role: Role, is_controller: bool) -> Result<(), String> {
+    check(principal, Role::Admin, is_controller).or_else(|_| check(principal, role, is_controller))
+}
+
+/// Checks the caller holds `role` or is an admin, for use before the owner of
+/// the record is known; `require_owner` still has to follow.
+pub fn require_role_or_admin(role: Role) -> Result<Principal, String> {
+    let caller = caller();
+    check_role_or_admin(&caller, role, is_controller(&caller))?;
+    Ok(caller)
+}
+
+fn check_owner(principal: &Principal, role: Role, owner: Principal, is_controller: bool) -> Result<(), String> {
+    if check(principal, Role::Admin, is_controller).is_ok() {
+        return Ok(());
+    }
+    check(principal, role, is_controller)?;
+    if owner == *principal {
+        Ok(())
+    } else {
+        Err(format!("Caller {} is neither the owner nor an admin", principal))
+    }
+}
+
+/// Checks the caller is `owner` and holds `role`, or is an admin.
+pub fn require_owner(role: Role, owner: Principal) -> Result<Principal, String> {
+    let caller = caller();
+    check_owner(&caller, role, owner, is_controller(&caller))?;
+    Ok(caller)
+}
+
+pub fn require_controller() -> Result<Principal, String> {
+    let caller = caller();
+    if is_controller(&caller) {
+        Ok(caller)
+    } else {
+        Err("Caller is not a controller".to_string())
+    }
+}
+
+pub fn grant(principal: Principal, role: Role) -> Result<(), String> {
+    if principal == Principal::anonymous() {
+        return Err("Roles cannot be granted to the anonymous principal".to_string());
+    }
+    let mut roles = roles_of(&principal);
+    if !roles.contains(&role) {
+        roles.push(role);
+        roles.sort();
+        ROLES.with(|r| r.borrow_mut().insert(principal, RoleSet(roles)));
+    }
+    Ok(())
+}
+
+pub fn revoke(principal: Principal, role: Role) {
+    let mut roles = roles_of(&principal);
+    roles.retain(|r| *r != role);
+    ROLES.with(|r| {
+        let mut grants = r.borrow_mut();
+        if roles.is_empty() {
+            grants.remove(&principal);
+        } else {
+            grants.insert(principal, RoleSet(roles));
+        }
+    });
+}
+
+pub fn list() -> Vec<RoleGrant> {
+    ROLES.with(|r| {
+        r.borrow()
+            .iter()
+            .map(|(principal, set)| RoleGrant { principal, roles: set.0 })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_granted_roles_pass_for_non_controllers() {
+        let issuer = Principal::from_slice(&[1; 29]);
+        assert!(check(&issuer, Role::Issuer, false).is_err());
+
+        grant(issuer, Role::Issuer).unwrap();
+        assert!(check(&issuer, Role::Issuer, false).is_ok());
+        assert!(check(&issuer, Role::Burner, false).is_err());
+        assert!(check(&issuer, Role::Subscriber, false).is_err());
+        assert!(check(&issuer, Role::Admin, true).is_ok());
+
+        revoke(issuer, Role::Issuer);
+        assert!(check(&issuer, Role::Issuer, false).is_err());
+        assert!(roles_of(&issuer).is_empty());
+        assert!(grant(Principal::anonymous(), Role::Admin).is_err());
+
+        let other = Principal::from_slice(&[2; 29]);
+        grant(issuer, Role::Issuer).unwrap();
+        assert!(check_owner(&issuer, Role::Issuer, issuer, false).is_ok());
+        assert!(check_owner(&issuer, Role::Issuer, other, false).is_err());
+        let outsider = Principal::from_slice(&[3; 29]);
+        assert!(check_role_or_admin(&outsider, Role::Issuer, false).is_err());
+        grant(other, Role::Admin).unwrap();
+        assert!(check_owner(&other, Role::Issuer, issuer, false).is_ok());
+        assert!(check_role_or_admin(&other, Role::Issuer, false).is_ok());
+    }
+}
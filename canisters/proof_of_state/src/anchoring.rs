@@ -0,0 +1,154 @@
+//! Anchor lifecycle for batches.
+//!
+//! A batch starts `Unanchored`, becomes `Broadcast` once the BTC signer hands
+//! back a txid, and moves to `Confirmed` when the transaction is mined. A
+//! confirmed anchor keeps being polled until it reaches the configured depth,
+//! at which point it is final and drops out of the tracking set. Only batches
+//! that `needs_anchor` are broadcast, so a transaction in flight is never
+//! doubled up.
+//!
+//! Every batch waiting for its first broadcast sits in the anchor queue with an
+//! `AnchorRetry`; failed attempts push `next_attempt_at` out with exponential
+//! backoff, and a successful broadcast removes the entry.
+
+use candid::{CandidType, Deserialize};
+pub use proof_of_state_verifier::AnchorProof;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AnchorStatus {
+    Unanchored,
+    Broadcast { txid: String, broadcast_at: u64 },
+    Confirmed { txid: String, block_height: u64, confirmations: u32 },
+    Failed { reason: String, failed_at: u64 },
+}
+
+const RETRY_BASE_SECS: u64 = 60;
+const RETRY_MAX_SECS: u64 = 6 * 60 * 60;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AnchorRetry {
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AnchorQueueEntry {
+    pub batch_root: String,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+impl AnchorRetry {
+    pub fn new(now: u64) -> Self {
+        AnchorRetry { attempts: 0, next_attempt_at: now, last_error: None }
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        now >= self.next_attempt_at
+    }
+
+    pub fn failed(&self, error: String, now: u64) -> AnchorRetry {
+        let attempts = self.attempts + 1;
+        AnchorRetry {
+            attempts,
+            next_attempt_at: now + backoff_secs(attempts) * NANOS_PER_SEC,
+            last_error: Some(error),
+        }
+    }
+}
+
+// 1m, 2m, 4m, ... capped at 6h
+fn backoff_secs(attempts: u32) -> u64 {
+    let exponent = attempts.saturating_sub(1).min(32);
+    RETRY_BASE_SECS.saturating_mul(1u64 << exponent).min(RETRY_MAX_SECS)
+}
+
+// Mirrors btc_signer_psbt's TransactionStatus record
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransactionStatus {
+    pub confirmed: bool,
+    pub block_height: Option<u64>,
+    pub confirmations: u32,
+}
+
+impl AnchorStatus {
+    /// The transaction currently standing in for this batch on BTC, if any.
+    pub fn txid(&self) -> Option<&str> {
+        match self {
+            AnchorStatus::Broadcast { txid, .. } | AnchorStatus::Confirmed { txid, .. } => Some(txid),
+            AnchorStatus::Unanchored | AnchorStatus::Failed { .. } => None,
+        }
+    }
+
+    pub fn block_height(&self) -> Option<u64> {
+        match self {
+            AnchorStatus::Confirmed { block_height, .. } => Some(*block_height),
+            _ => None,
+        }
+    }
+
+    pub fn needs_anchor(&self) -> bool {
+        matches!(self, AnchorStatus::Unanchored | AnchorStatus::Failed { .. })
+    }
+
+    pub fn is_final(&self, required_confirmations: u32) -> bool {
+        matches!(self, AnchorStatus::Confirmed { confirmations, .. } if *confirmations >= required_confirmations)
+    }
+
+    /// Status after polling the tracked transaction.
+    pub fn observe(&self, status: &TransactionStatus, now: u64) -> AnchorStatus {
+        let Some(txid) = self.txid() else {
+            return self.clone();
+        };
+        match (status.confirmed, status.block_height) {
+            (true, Some(block_height)) => AnchorStatus::Confirmed {
+                txid: txid.to_string(),
+                block_height,
+                confirmations: status.confirmations,
+            },
+            // Dropped out of a block (reorg): back to waiting for inclusion
+            _ if matches!(self, AnchorStatus::Confirmed { .. }) => AnchorStatus::Broadcast {
+                txid: txid.to_string(),
+                broadcast_at: now,
+            },
+            _ => self.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_then_confirm_to_depth() {
+        let status = AnchorStatus::Broadcast { txid: "tx1".to_string(), broadcast_at: 1 };
+        assert_eq!(status.txid(), Some("tx1"));
+
+        let mined = TransactionStatus { confirmed: true, block_height: Some(10), confirmations: 1 };
+        let status = status.observe(&mined, 2);
+        assert_eq!(status.block_height(), Some(10));
+        assert!(!status.is_final(6));
+
+        let deep = TransactionStatus { confirmations: 6, ..mined };
+        assert!(status.observe(&deep, 3).is_final(6));
+    }
+
+    #[test]
+    fn failed_attempts_back_off_exponentially() {
+        let retry = AnchorRetry::new(0);
+        assert!(retry.is_due(0));
+
+        let first = retry.failed("boom".to_string(), 0);
+        assert_eq!(first.attempts, 1);
+        assert!(!first.is_due(59 * NANOS_PER_SEC));
+        assert!(first.is_due(60 * NANOS_PER_SEC));
+
+        let second = first.failed("boom".to_string(), 0);
+        assert_eq!(second.next_attempt_at, 120 * NANOS_PER_SEC);
+        assert_eq!(backoff_secs(40), RETRY_MAX_SECS);
+    }
+}
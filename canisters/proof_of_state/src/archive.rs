@@ -0,0 +1,366 @@
+//! Offloading old batches to archive canisters.
+//!
+//! Once `archive_policy` is set and a controller has uploaded the
+//! proof_of_state_archive wasm, confirmed batches older than
+//! `archive_after_secs` move, with their receipts and SPV proof, into archive
+//! canisters this canister spawns and controls, ICRC-3 style. A batch is only
+//! moved once nothing will touch it again: its BTC anchor is final with its
+//! proof fetched, it is signed and every chain anchor is settled.
+//!
+//! What stays behind is a lightweight index: `BATCH_ROOTS` (root -> seq),
+//! `RECEIPT_BATCH` (receipt -> seq), receipt events and the MMR, plus the
+//! archive holding each archived batch. `get_receipt` and `verify_receipt`
+//! follow it to the right archive with composite queries, which works because
+//! archives are created on this canister's subnet. Archived receipts drop out
+//! of the certified tree and the iQube and issuer listings.
+//!
+//! Host tests have no management canister; there archives are kept in memory.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::{Cell, RefCell};
+
+use crate::certification;
+use crate::chains;
+use crate::config::{self, ArchivePolicy};
+use crate::storage::{self, Memory};
+use crate::{MerkleBatch, Receipt};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+// Batches moved per run, to stay well inside the instruction limit
+const MAX_BATCHES_PER_RUN: usize = 16;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchiveInfo {
+    pub canister_id: Principal,
+    // Lowest and highest batch sequence numbers held; batches still local may fall in between
+    pub start_seq: Option<u64>,
+    pub end_seq: Option<u64>,
+    pub batch_count: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BatchLocation {
+    pub seq: u64,
+    // None while the batch is still held locally
+    pub archive: Option<Principal>,
+}
+
+// Mirror the records of proof_of_state_archive.did
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct BatchRecord {
+    seq: u64,
+    root: String,
+    batch: Vec<u8>,
+    anchor_proof: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct ReceiptRecord {
+    id: String,
+    seq: u64,
+    receipt: Vec<u8>,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+#[derive(CandidType, Deserialize)]
+struct ArchiveInitArgs {
+    parent: Principal,
+}
+
+thread_local! {
+    // Spawned archives in creation order; the last one receives new batches
+    static ARCHIVES: RefCell<StableBTreeMap<u64, ArchiveInfo, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ARCHIVES_MEMORY)));
+    // Batch sequence number -> the archive holding it
+    static ARCHIVED: RefCell<StableBTreeMap<u64, Principal, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ARCHIVED_BATCHES_MEMORY)));
+    static WASM: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::ARCHIVE_WASM_MEMORY), Vec::new())
+            .expect("failed to init archive wasm cell")
+    );
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+// A host archive's appended batches, each with its receipts
+#[cfg(not(target_arch = "wasm32"))]
+type HostArchive = Vec<(BatchRecord, Vec<ReceiptRecord>)>;
+
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    static HOST_ARCHIVES: RefCell<std::collections::BTreeMap<Principal, HostArchive>> =
+        const { RefCell::new(std::collections::BTreeMap::new()) };
+}
+
+pub fn set_wasm(wasm: Vec<u8>) -> Result<(), String> {
+    // Plain or gzipped wasm modules
+    if !wasm.starts_with(b"\0asm") && !wasm.starts_with(&[0x1f, 0x8b]) {
+        return Err("Not a wasm module".to_string());
+    }
+    WASM.with(|w| w.borrow_mut().set(wasm)).map(|_| ()).map_err(|e| format!("Failed to store archive wasm: {:?}", e))
+}
+
+pub fn archives() -> Vec<ArchiveInfo> {
+    ARCHIVES.with(|a| a.borrow().iter().map(|(_, info)| info).collect())
+}
+
+pub fn archive_of(seq: u64) -> Option<Principal> {
+    ARCHIVED.with(|a| a.borrow().get(&seq))
+}
+
+pub fn last_archived_seq() -> Option<u64> {
+    ARCHIVED.with(|a| a.borrow().last_key_value().map(|(seq, _)| seq))
+}
+
+pub fn locate(root: &str) -> Option<BatchLocation> {
+    let seq = crate::BATCH_ROOTS.with(|r| r.borrow().get(&root.to_string()))?;
+    Some(BatchLocation { seq, archive: archive_of(seq) })
+}
+
+// Nothing will change the batch again once it is here
+fn is_settled(seq: u64, batch: &MerkleBatch, required_confirmations: u32) -> bool {
+    batch.anchor_status.is_final(required_confirmations)
+        && !crate::TRACKED_ANCHORS.with(|t| t.borrow().contains_key(&seq))
+        && !crate::ANCHOR_QUEUE.with(|q| q.borrow().contains_key(&seq))
+        && !crate::SIGNING_QUEUE.with(|s| s.borrow().contains_key(&seq))
+        && chains::is_settled(seq)
+}
+
+// Oldest batches due for archiving; batches are cut in time order, so the scan
+// stops at the first one that is too young
+fn due(policy: &ArchivePolicy, now: u64) -> Vec<u64> {
+    let cutoff = now.saturating_sub(policy.archive_after_secs.saturating_mul(NANOS_PER_SEC));
+    let required = crate::scheduler::state().policy.required_confirmations;
+    crate::BATCHES.with(|b| {
+        b.borrow()
+            .iter()
+            .take_while(|(_, batch)| batch.created_at <= cutoff)
+            .filter(|(seq, batch)| is_settled(*seq, batch, required))
+            .map(|(seq, _)| seq)
+            .take(MAX_BATCHES_PER_RUN)
+            .collect()
+    })
+}
+
+pub fn has_due(now: u64) -> bool {
+    config::get().archive_policy.is_some_and(|policy| !due(&policy, now).is_empty())
+}
+
+fn records(seq: u64, batch: &MerkleBatch) -> (BatchRecord, Vec<ReceiptRecord>) {
+    let anchor_proof = crate::ANCHOR_PROOFS.with(|p| p.borrow().get(&seq));
+    let record = BatchRecord {
+        seq,
+        root: batch.root.clone(),
+        batch: candid::encode_one(batch).expect("failed to encode batch"),
+        anchor_proof: anchor_proof.map(|proof| candid::encode_one(proof).expect("failed to encode anchor proof")),
+    };
+    let receipts = crate::receipts_by_id(batch.receipts.iter().map(|receipt| receipt.id.clone()).collect())
+        .into_iter()
+        .map(|receipt| ReceiptRecord {
+            id: receipt.id.clone(),
+            seq,
+            receipt: candid::encode_one(receipt).expect("failed to encode receipt"),
+        })
+        .collect();
+    (record, receipts)
+}
+
+/// Moves every due batch into the current archive, spawning archives as they
+/// fill up. Returns how many batches were moved.
+pub async fn archive_due() -> Result<u64, String> {
+    if ACTIVE.with(|a| a.replace(true)) {
+        return Err("Archiving already in progress".to_string());
+    }
+    let result = archive_due_inner().await;
+    ACTIVE.with(|a| a.set(false));
+    result
+}
+
+async fn archive_due_inner() -> Result<u64, String> {
+    let policy = config::get().archive_policy.ok_or_else(|| "Archiving is not configured".to_string())?;
+    let mut moved = 0;
+    for seq in due(&policy, crate::now()) {
+        let Some(batch) = crate::BATCHES.with(|b| b.borrow().get(&seq)) else { continue };
+        let (index, archive) = current_archive(&policy).await?;
+        let (record, receipts) = records(seq, &batch);
+        append(archive.canister_id, &record, &receipts).await?;
+
+        // Re-read after the await: only drop what the archive now holds
+        let current = crate::BATCHES.with(|b| b.borrow().get(&seq));
+        if current.map(|batch| records(seq, &batch)) != Some((record, receipts)) {
+            continue;
+        }
+        forget(seq, &batch);
+        ARCHIVED.with(|a| a.borrow_mut().insert(seq, archive.canister_id));
+        ARCHIVES.with(|a| {
+            let mut archives = a.borrow_mut();
+            if let Some(mut info) = archives.get(&index) {
+                info.start_seq = Some(info.start_seq.map_or(seq, |start| start.min(seq)));
+                info.end_seq = Some(info.end_seq.map_or(seq, |end| end.max(seq)));
+                info.batch_count += 1;
+                archives.insert(index, info);
+            }
+        });
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+// Drops the batch, its receipts and its proof from local storage
+fn forget(seq: u64, batch: &MerkleBatch) {
+    crate::RECEIPTS.with(|r| {
+        let mut receipts = r.borrow_mut();
+        for receipt in &batch.receipts {
+            receipts.remove(&receipt.id);
+            certification::forget_receipt(&receipt.id);
+        }
+    });
+    crate::BATCHES.with(|b| b.borrow_mut().remove(&seq));
+    crate::ANCHOR_PROOFS.with(|p| p.borrow_mut().remove(&seq));
+    certification::forget_batch(&batch.root);
+    certification::commit();
+}
+
+// The archive new batches go to, spawning one when the last is full
+async fn current_archive(policy: &ArchivePolicy) -> Result<(u64, ArchiveInfo), String> {
+    let last = ARCHIVES.with(|a| a.borrow().last_key_value());
+    let (index, info) = match last {
+        Some((index, info)) if info.batch_count < policy.batches_per_archive => (index, info),
+        last => {
+            let index = last.map(|(index, _)| index + 1).unwrap_or(0);
+            let canister_id = create(policy, index).await?;
+            let info = ArchiveInfo { canister_id, start_seq: None, end_seq: None, batch_count: 0 };
+            ARCHIVES.with(|a| a.borrow_mut().insert(index, info.clone()));
+            (index, info)
+        }
+    };
+    // An empty archive may have been created by a run whose install failed
+    if info.batch_count == 0 {
+        install(info.canister_id).await?;
+    }
+    Ok((index, info))
+}
+
+async fn create(policy: &ArchivePolicy, index: u64) -> Result<Principal, String> {
+    if WASM.with(|w| w.borrow().get().is_empty()) {
+        return Err("No archive wasm uploaded".to_string());
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        use ic_cdk::api::management_canister::main::{create_canister, CanisterSettings, CreateCanisterArgument};
+        let _ = index;
+        let settings = CanisterSettings { controllers: Some(vec![ic_cdk::id()]), ..Default::default() };
+        let (record,) = create_canister(CreateCanisterArgument { settings: Some(settings) }, policy.archive_cycles as u128)
+            .await
+            .map_err(|(code, msg)| format!("create_canister failed: {:?} - {}", code, msg))?;
+        Ok(record.canister_id)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = policy;
+        let mut id = b"archive".to_vec();
+        id.extend_from_slice(&index.to_be_bytes());
+        Ok(Principal::from_slice(&id))
+    }
+}
+
+// (Re)installs the archive code; only ever done while the archive is empty
+async fn install(canister_id: Principal) -> Result<(), String> {
+    let wasm = WASM.with(|w| w.borrow().get().clone());
+    #[cfg(target_arch = "wasm32")]
+    {
+        use ic_cdk::api::management_canister::main::{install_code, CanisterInstallMode, InstallCodeArgument};
+        let arg = candid::encode_one(ArchiveInitArgs { parent: ic_cdk::id() }).expect("failed to encode init args");
+        install_code(InstallCodeArgument { mode: CanisterInstallMode::Reinstall, canister_id, wasm_module: wasm, arg })
+            .await
+            .map_err(|(code, msg)| format!("install_code failed: {:?} - {}", code, msg))
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = wasm;
+        HOST_ARCHIVES.with(|h| h.borrow_mut().insert(canister_id, Vec::new()));
+        Ok(())
+    }
+}
+
+async fn append(archive: Principal, batch: &BatchRecord, receipts: &[ReceiptRecord]) -> Result<(), String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let (result,): (Result<(), String>,) = ic_cdk::call(archive, "append_batch", (batch, receipts))
+            .await
+            .map_err(|(code, msg)| format!("Archive call failed: {:?} - {}", code, msg))?;
+        result
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        HOST_ARCHIVES.with(|h| {
+            let mut archives = h.borrow_mut();
+            let stored = archives.get_mut(&archive).ok_or_else(|| format!("Archive {} not installed", archive))?;
+            stored.retain(|(record, _)| record.seq != batch.seq);
+            stored.push((batch.clone(), receipts.to_vec()));
+            Ok(())
+        })
+    }
+}
+
+async fn fetch_receipt_record(archive: Principal, receipt_id: &str) -> Result<Option<ReceiptRecord>, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let (record,): (Option<ReceiptRecord>,) = ic_cdk::call(archive, "get_receipt", (receipt_id.to_string(),))
+            .await
+            .map_err(|(code, msg)| format!("Archive call failed: {:?} - {}", code, msg))?;
+        Ok(record)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Ok(HOST_ARCHIVES.with(|h| {
+            h.borrow()
+                .get(&archive)
+                .and_then(|stored| stored.iter().flat_map(|(_, receipts)| receipts).find(|r| r.id == receipt_id).cloned())
+        }))
+    }
+}
+
+async fn fetch_batch_record(archive: Principal, seq: u64) -> Result<Option<BatchRecord>, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let (record,): (Option<BatchRecord>,) = ic_cdk::call(archive, "get_batch", (seq,))
+            .await
+            .map_err(|(code, msg)| format!("Archive call failed: {:?} - {}", code, msg))?;
+        Ok(record)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Ok(HOST_ARCHIVES.with(|h| {
+            h.borrow()
+                .get(&archive)
+                .and_then(|stored| stored.iter().find(|(record, _)| record.seq == seq).map(|(record, _)| record.clone()))
+        }))
+    }
+}
+
+/// The receipt from the archive holding its batch; None if it was never archived.
+pub async fn fetch_receipt(receipt_id: &str) -> Result<Option<Receipt>, String> {
+    let Some(seq) = crate::RECEIPT_BATCH.with(|r| r.borrow().get(&receipt_id.to_string())) else { return Ok(None) };
+    let Some(archive) = archive_of(seq) else { return Ok(None) };
+    let Some(record) = fetch_receipt_record(archive, receipt_id).await? else { return Ok(None) };
+    candid::decode_one(&record.receipt).map(Some).map_err(|e| format!("Failed to decode archived receipt: {}", e))
+}
+
+pub async fn fetch_batch(seq: u64) -> Result<Option<MerkleBatch>, String> {
+    let Some(archive) = archive_of(seq) else { return Ok(None) };
+    let Some(record) = fetch_batch_record(archive, seq).await? else { return Ok(None) };
+    candid::decode_one(&record.batch).map(Some).map_err(|e| format!("Failed to decode archived batch: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_wasm_modules_are_accepted() {
+        assert!(set_wasm(b"not wasm".to_vec()).is_err());
+        set_wasm(b"\0asm\x01\0\0\0".to_vec()).unwrap();
+        assert!(!WASM.with(|w| w.borrow().get().is_empty()));
+    }
+}
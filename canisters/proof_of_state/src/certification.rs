@@ -0,0 +1,361 @@
+//! Certified data over receipts and batch roots.
+//!
+//! The canister keeps a hash tree with two labeled subtrees and, once the
+//! ICRC-3 block log has a block, the two tip labels ICRC-3 requires, and sets
+//! its root hash as the canister's certified data after every change:
+//!
+//! ```text
+//! batches/<root hex>    -> root hash bytes
+//! last_block_hash       -> hash of the newest block
+//! last_block_index      -> its index, LEB128
+//! receipts/<receipt id> -> receipt leaf hash
+//! ```
+//!
+//! Certified queries return the IC certificate and a CBOR witness for the
+//! requested key, so a client can check the returned record against the
+//! certificate without an update call. The tree lives on the heap. Receipt
+//! leaf hashes are also kept in stable memory, so rebuilding the tree after an
+//! upgrade copies stored hashes rather than decoding and rehashing receipts.
+//! The rebuild copies at most `REBUILD_CHUNK` entries per message, continuing
+//! on a timer; until it finishes the certified data is left alone and
+//! certificates are refused. Archived receipts and batches leave the tree.
+
+use candid::{CandidType, Deserialize};
+use ic_certified_map::{fork, labeled, labeled_hash, AsHashTree, Hash, HashTree, RbTree};
+use serde::Serialize;
+use std::borrow::Cow;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::Bound;
+
+use crate::storage::{self, Memory};
+use crate::{BatchSummary, Receipt};
+
+const BATCHES_LABEL: &[u8] = b"batches";
+const RECEIPTS_LABEL: &[u8] = b"receipts";
+const LAST_BLOCK_HASH_LABEL: &[u8] = b"last_block_hash";
+const LAST_BLOCK_INDEX_LABEL: &[u8] = b"last_block_index";
+// Tree entries copied from stable memory per message while rebuilding
+const REBUILD_CHUNK: usize = 5_000;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CertifiedReceipt {
+    pub receipt: Receipt,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CertifiedBatch {
+    pub batch: BatchSummary,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
+
+// Progress of a rebuild: the last key copied in the current phase
+#[derive(Clone)]
+enum Rebuild {
+    Receipts(Option<String>),
+    Batches(Option<String>),
+}
+
+thread_local! {
+    static RECEIPTS: RefCell<RbTree<String, Hash>> = const { RefCell::new(RbTree::new()) };
+    static BATCHES: RefCell<RbTree<String, Hash>> = const { RefCell::new(RbTree::new()) };
+    // Index and hash of the newest ICRC-3 block
+    static TIP: RefCell<Option<(u64, Hash)>> = const { RefCell::new(None) };
+    // Durable copy of RECEIPTS, read back by `rebuild`
+    static LEAVES: RefCell<StableBTreeMap<String, Hash, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::CERTIFIED_LEAVES_MEMORY)));
+    // Some while the heap tree is still being rebuilt
+    static REBUILD: RefCell<Option<Rebuild>> = const { RefCell::new(None) };
+}
+
+fn rebuilding() -> bool {
+    REBUILD.with(|r| r.borrow().is_some())
+}
+
+pub fn certify_receipt(receipt_id: &str, leaf: Hash) {
+    LEAVES.with(|l| l.borrow_mut().insert(receipt_id.to_string(), leaf));
+    RECEIPTS.with(|r| r.borrow_mut().insert(receipt_id.to_string(), leaf));
+}
+
+pub fn certify_batch(root: &str, root_hash: Hash) {
+    BATCHES.with(|b| b.borrow_mut().insert(root.to_string(), root_hash));
+}
+
+pub fn forget_receipt(receipt_id: &str) {
+    LEAVES.with(|l| l.borrow_mut().remove(&receipt_id.to_string()));
+    RECEIPTS.with(|r| r.borrow_mut().delete(receipt_id.as_bytes()));
+}
+
+pub fn forget_batch(root: &str) {
+    BATCHES.with(|b| b.borrow_mut().delete(root.as_bytes()));
+}
+
+pub fn set_tip(index: u64, hash: Hash) {
+    TIP.with(|t| *t.borrow_mut() = Some((index, hash)));
+}
+
+fn leb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+// The tip labels, revealed or pruned; None before the first block
+fn tip_nodes<'a>(reveal: bool) -> Option<[HashTree<'a>; 2]> {
+    let (index, hash) = TIP.with(|t| *t.borrow())?;
+    let nodes = [
+        labeled(LAST_BLOCK_HASH_LABEL, HashTree::Leaf(Cow::Owned(hash.to_vec()))),
+        labeled(LAST_BLOCK_INDEX_LABEL, HashTree::Leaf(Cow::Owned(leb128(index)))),
+    ];
+    Some(if reveal { nodes } else { nodes.map(|node| HashTree::Pruned(node.reconstruct())) })
+}
+
+// Labels sort left to right: batches, last_block_hash, last_block_index, receipts
+fn assemble<'a>(batches: HashTree<'a>, receipts: HashTree<'a>, tip: Option<[HashTree<'a>; 2]>) -> HashTree<'a> {
+    match tip {
+        None => fork(batches, receipts),
+        Some([hash, index]) => fork(fork(batches, hash), fork(index, receipts)),
+    }
+}
+
+fn pruned_batches<'a>() -> HashTree<'a> {
+    HashTree::Pruned(labeled_hash(BATCHES_LABEL, &BATCHES.with(|b| b.borrow().root_hash())))
+}
+
+fn pruned_receipts<'a>() -> HashTree<'a> {
+    HashTree::Pruned(labeled_hash(RECEIPTS_LABEL, &RECEIPTS.with(|r| r.borrow().root_hash())))
+}
+
+pub fn root_hash() -> Hash {
+    assemble(pruned_batches(), pruned_receipts(), tip_nodes(false)).reconstruct()
+}
+
+/// Publishes the current root hash as the canister's certified data, unless
+/// the tree is still being rebuilt.
+pub fn commit() {
+    if rebuilding() {
+        return;
+    }
+    let root = root_hash();
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::set_certified_data(&root);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = root;
+    }
+}
+
+/// The IC certificate for the current certified data; only present in queries.
+pub fn certificate() -> Result<Vec<u8>, String> {
+    if rebuilding() {
+        return Err("The certified tree is still being rebuilt after an upgrade".to_string());
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::data_certificate().ok_or_else(|| "Certificates are only available in query calls".to_string())
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Err("Certificates are only available in query calls".to_string())
+    }
+}
+
+fn encode(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::Serializer::new(Vec::new());
+    serializer.self_describe().expect("failed to write CBOR tag");
+    tree.serialize(&mut serializer).expect("failed to encode witness");
+    serializer.into_inner()
+}
+
+pub fn receipt_witness(receipt_id: &str) -> Vec<u8> {
+    RECEIPTS.with(|r| {
+        let receipts = r.borrow();
+        let receipts = labeled(RECEIPTS_LABEL, receipts.witness(receipt_id.as_bytes()));
+        encode(&assemble(pruned_batches(), receipts, tip_nodes(false)))
+    })
+}
+
+pub fn batch_witness(root: &str) -> Vec<u8> {
+    BATCHES.with(|b| {
+        let batches = b.borrow();
+        let batches = labeled(BATCHES_LABEL, batches.witness(root.as_bytes()));
+        encode(&assemble(batches, pruned_receipts(), tip_nodes(false)))
+    })
+}
+
+/// CBOR tree revealing only the ICRC-3 tip labels, for `icrc3_get_tip_certificate`.
+pub fn tip_witness() -> Option<Vec<u8>> {
+    let tip = tip_nodes(true)?;
+    Some(encode(&assemble(pruned_batches(), pruned_receipts(), Some(tip))))
+}
+
+/// Starts rebuilding the tree from stable memory, e.g. after an upgrade. Only
+/// stored hashes and batch roots are read; receipts and batches are never
+/// decoded. The first chunk is copied right away and the rest on timers.
+pub fn start_rebuild() {
+    reset();
+    continue_rebuild();
+}
+
+fn reset() {
+    RECEIPTS.with(|r| *r.borrow_mut() = RbTree::new());
+    BATCHES.with(|b| *b.borrow_mut() = RbTree::new());
+    REBUILD.with(|r| *r.borrow_mut() = Some(Rebuild::Receipts(None)));
+}
+
+fn continue_rebuild() {
+    if !rebuild_step(REBUILD_CHUNK) {
+        ic_cdk_timers::set_timer(std::time::Duration::ZERO, continue_rebuild);
+    }
+}
+
+// Keys after `cursor`, or all of them when the phase has just started
+fn after<K: Clone>(cursor: &Option<K>) -> (Bound<K>, Bound<K>) {
+    match cursor {
+        Some(key) => (Bound::Excluded(key.clone()), Bound::Unbounded),
+        None => (Bound::Unbounded, Bound::Unbounded),
+    }
+}
+
+/// Copies up to `budget` entries into the tree; true once the rebuild is done
+/// and the root has been committed. Writes made in the meantime go to both
+/// the tree and stable memory, so they are never lost or doubled.
+fn rebuild_step(budget: usize) -> bool {
+    let Some(state) = REBUILD.with(|r| r.borrow().clone()) else {
+        return true;
+    };
+    let next = match state {
+        Rebuild::Receipts(cursor) => {
+            let chunk: Vec<(String, Hash)> = LEAVES.with(|l| l.borrow().range(after(&cursor)).take(budget).collect());
+            RECEIPTS.with(|r| {
+                let mut receipts = r.borrow_mut();
+                for (id, leaf) in &chunk {
+                    receipts.insert(id.clone(), *leaf);
+                }
+            });
+            if chunk.len() < budget {
+                Rebuild::Batches(None)
+            } else {
+                Rebuild::Receipts(chunk.last().map(|(id, _)| id.clone()))
+            }
+        }
+        Rebuild::Batches(cursor) => {
+            let chunk: Vec<(String, u64)> =
+                crate::BATCH_ROOTS.with(|r| r.borrow().range(after(&cursor)).take(budget).collect());
+            for (root, seq) in &chunk {
+                if crate::archive::archive_of(*seq).is_some() {
+                    continue;
+                }
+                if let Ok(root_hash) = crate::merkle::decode_hash(root) {
+                    certify_batch(root, root_hash);
+                }
+            }
+            if chunk.len() < budget {
+                REBUILD.with(|r| *r.borrow_mut() = None);
+                if let Some((index, hash)) = crate::icrc3::tip() {
+                    set_tip(index, hash);
+                }
+                commit();
+                return true;
+            }
+            Rebuild::Batches(chunk.last().map(|(root, _)| root.clone()))
+        }
+    };
+    REBUILD.with(|r| *r.borrow_mut() = Some(next));
+    false
+}
+
+/// Rebuilds the whole tree in one go, as if every timer had fired.
+#[cfg(test)]
+pub fn rebuild() {
+    reset();
+    while !rebuild_step(REBUILD_CHUNK) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn witness_reconstructs_certified_root() {
+        certify_receipt("receipt_0", [1; 32]);
+        certify_receipt("receipt_1", [2; 32]);
+        certify_batch("aa", [3; 32]);
+        let root = root_hash();
+
+        let receipts = RECEIPTS.with(|r| r.borrow().root_hash());
+        let witness = BATCHES.with(|b| {
+            fork(
+                labeled(BATCHES_LABEL, b.borrow().witness(b"aa")),
+                HashTree::Pruned(labeled_hash(RECEIPTS_LABEL, &receipts)),
+            )
+            .reconstruct()
+        });
+        assert_eq!(witness, root);
+        assert!(!receipt_witness("receipt_1").is_empty());
+        assert!(tip_witness().is_none());
+
+        certify_receipt("receipt_1", [9; 32]);
+        assert_ne!(root_hash(), root);
+
+        // The tip labels join the tree with the first block
+        let before = root_hash();
+        set_tip(300, [4; 32]);
+        assert_ne!(root_hash(), before);
+        let revealed = assemble(pruned_batches(), pruned_receipts(), tip_nodes(true)).reconstruct();
+        assert_eq!(revealed, root_hash());
+        assert_eq!(leb128(300), vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn rebuild_restores_receipts_from_stored_leaves() {
+        certify_receipt("receipt_0", [1; 32]);
+        certify_receipt("receipt_1", [2; 32]);
+        certify_receipt("receipt_2", [3; 32]);
+        forget_receipt("receipt_2");
+        let root = root_hash();
+
+        // An upgrade starts from an empty heap tree
+        RECEIPTS.with(|r| *r.borrow_mut() = RbTree::new());
+        assert_ne!(root_hash(), root);
+        rebuild();
+        assert_eq!(root_hash(), root);
+    }
+
+    #[test]
+    fn rebuild_copies_a_bounded_chunk_per_message() {
+        for i in 0..5 {
+            certify_receipt(&format!("receipt_{}", i), [i; 32]);
+        }
+        let root = root_hash();
+        reset();
+
+        let tree_len = || RECEIPTS.with(|r| r.borrow().iter().count());
+        assert!(!rebuild_step(2));
+        assert_eq!(tree_len(), 2);
+        // Nothing is certified from a half-built tree
+        assert!(certificate().unwrap_err().contains("rebuilt"));
+        // Receipts issued mid-rebuild land in the tree straight away
+        certify_receipt("receipt_9", [9; 32]);
+        assert_eq!(tree_len(), 3);
+        assert!(!rebuild_step(2));
+        assert_eq!(tree_len(), 5);
+        while !rebuild_step(2) {}
+        assert_eq!(tree_len(), 6);
+        forget_receipt("receipt_9");
+        assert_eq!(root_hash(), root);
+        assert!(!rebuilding());
+    }
+}
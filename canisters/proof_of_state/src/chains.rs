@@ -0,0 +1,210 @@
+//! Anchoring batch commitments to chains other than BTC.
+//!
+//! BTC stays the primary anchor (`MerkleBatch::anchor_status`) and the only one
+//! backed by SPV and OTS proofs. Each deployment can additionally enable
+//! targets for EVM chains and Solana; every batch cut while a target is enabled
+//! gets a `ChainAnchor` of its own that walks the same `AnchorStatus` states
+//! and retry backoff as the BTC anchor.
+//!
+//! Only Mock anchoring exists so far: no canister in this repo publishes a
+//! commitment to an EVM chain or Solana yet. Targets can therefore only be set
+//! on Mock-mode deployments, and each anchor gets a fabricated transaction ID
+//! that is never confirmed, as for BTC in Mock mode.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::anchoring::{AnchorRetry, AnchorStatus};
+use crate::config::{self, AnchorMode};
+use crate::icrc3;
+use crate::notifications::{self, Notification};
+use crate::storage::{self, Memory};
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+    Evm { chain_id: u32 },
+    Solana,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AnchorTarget {
+    pub chain: Chain,
+    pub enabled: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChainAnchor {
+    pub chain: Chain,
+    pub status: AnchorStatus,
+    pub mode: Option<AnchorMode>,
+}
+
+// Anchor keys are "<seq:020>/<chain key>" so a batch's anchors sort together
+type AnchorKey = String;
+
+thread_local! {
+    static TARGETS: RefCell<StableBTreeMap<String, AnchorTarget, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ANCHOR_TARGETS_MEMORY)));
+    // Chain anchors waiting for their first broadcast
+    static QUEUE: RefCell<StableBTreeMap<AnchorKey, AnchorRetry, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::CHAIN_ANCHOR_QUEUE_MEMORY)));
+}
+
+impl Chain {
+    pub fn key(&self) -> String {
+        match self {
+            Chain::Evm { chain_id } => format!("evm:{}", chain_id),
+            Chain::Solana => "solana".to_string(),
+        }
+    }
+}
+
+fn anchor_key(seq: u64, chain: &Chain) -> AnchorKey {
+    format!("{:020}/{}", seq, chain.key())
+}
+
+fn parse_key(key: &str) -> u64 {
+    key[..20].parse().expect("malformed chain anchor key")
+}
+
+pub fn set_target(target: AnchorTarget) -> Result<(), String> {
+    if config::mode() != AnchorMode::Mock {
+        return Err("Anchoring to other chains is only available in Mock mode".to_string());
+    }
+    if matches!(target.chain, Chain::Evm { chain_id: 0 }) {
+        return Err("EVM chain_id must be greater than zero".to_string());
+    }
+    TARGETS.with(|t| t.borrow_mut().insert(target.chain.key(), target));
+    Ok(())
+}
+
+pub fn remove_target(chain: &Chain) -> Result<(), String> {
+    TARGETS
+        .with(|t| t.borrow_mut().remove(&chain.key()))
+        .map(|_| ())
+        .ok_or_else(|| format!("No anchor target for {}", chain.key()))
+}
+
+pub fn targets() -> Vec<AnchorTarget> {
+    TARGETS.with(|t| t.borrow().iter().map(|(_, target)| target).collect())
+}
+
+fn target(chain: &Chain) -> Option<AnchorTarget> {
+    TARGETS.with(|t| t.borrow().get(&chain.key())).filter(|target| target.enabled)
+}
+
+/// Anchors for a new batch on every enabled target, each queued for broadcast.
+pub fn start(seq: u64, now: u64) -> Vec<ChainAnchor> {
+    let chains: Vec<Chain> = targets().into_iter().filter(|t| t.enabled).map(|t| t.chain).collect();
+    QUEUE.with(|q| {
+        let mut queue = q.borrow_mut();
+        for chain in &chains {
+            queue.insert(anchor_key(seq, chain), AnchorRetry::new(now));
+        }
+    });
+    chains.into_iter().map(|chain| ChainAnchor { chain, status: AnchorStatus::Unanchored, mode: None }).collect()
+}
+
+/// Whether every anchor of the batch has been broadcast.
+pub fn is_settled(seq: u64) -> bool {
+    let prefix = format!("{:020}/", seq);
+    !QUEUE.with(|q| q.borrow().range(prefix.clone()..).next().is_some_and(|(key, _)| key.starts_with(&prefix)))
+}
+
+pub fn has_due(now: u64) -> bool {
+    QUEUE.with(|q| q.borrow().iter().any(|(_, retry)| retry.is_due(now)))
+}
+
+// Applies `f` to the batch's anchor on `chain`, re-reading the batch first
+fn update_anchor(seq: u64, chain: &Chain, f: impl FnOnce(&mut ChainAnchor)) {
+    crate::BATCHES.with(|b| {
+        let mut batches = b.borrow_mut();
+        let Some(mut batch) = batches.get(&seq) else { return };
+        if let Some(anchor) = batch.chain_anchors.iter_mut().find(|a| a.chain == *chain) {
+            f(anchor);
+            batches.insert(seq, batch);
+        }
+    });
+}
+
+fn broadcast_queued(key: &AnchorKey, chain: Chain) -> Result<String, String> {
+    let seq = parse_key(key);
+    let Some(batch) = crate::BATCHES.with(|b| b.borrow().get(&seq)) else {
+        QUEUE.with(|q| q.borrow_mut().remove(key));
+        return Err(format!("Batch {} not found", seq));
+    };
+    let now = crate::now();
+    let txid = match batch.anchor_commitment() {
+        Ok(commitment) => format!("mock_{}_txid_{}", chain.key(), &commitment[..8]),
+        Err(e) => {
+            update_anchor(seq, &chain, |anchor| {
+                anchor.status = AnchorStatus::Failed { reason: e.clone(), failed_at: now };
+            });
+            QUEUE.with(|q| {
+                let mut queue = q.borrow_mut();
+                let retry = queue.get(key).unwrap_or_else(|| AnchorRetry::new(now));
+                queue.insert(key.clone(), retry.failed(e.clone(), now));
+            });
+            return Err(format!("Failed to anchor batch {} to {}: {}", batch.root, chain.key(), e));
+        }
+    };
+    update_anchor(seq, &chain, |anchor| {
+        anchor.status = AnchorStatus::Broadcast { txid: txid.clone(), broadcast_at: now };
+        anchor.mode = Some(AnchorMode::Mock);
+    });
+    let root = batch.root.clone();
+    icrc3::anchor_broadcast(seq, &root, Some(&chain), &txid, now);
+    notifications::publish(Notification::AnchorBroadcast { seq, root, chain: Some(chain), txid: txid.clone() }, now);
+    QUEUE.with(|q| q.borrow_mut().remove(key));
+    Ok(format!("Anchored batch {} to {} with txid: {}", batch.root, chain.key(), txid))
+}
+
+/// Broadcasts queued chain anchors whose target is enabled, oldest batch
+/// first; `due_only` respects retry backoff.
+pub fn anchor_queued(due_only: bool) -> Vec<Result<String, String>> {
+    // Anchors queued before a switch to Live wait until the mode is Mock again
+    if config::mode() != AnchorMode::Mock {
+        return Vec::new();
+    }
+    let now = crate::now();
+    let queued: Vec<AnchorKey> = QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .filter(|(_, retry)| !due_only || retry.is_due(now))
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    let mut results = Vec::with_capacity(queued.len());
+    for key in queued {
+        // Anchors for disabled targets wait in the queue until re-enabled
+        let chain = chain_of(&key);
+        if target(&chain).is_some() {
+            results.push(broadcast_queued(&key, chain));
+        }
+    }
+    results
+}
+
+fn chain_of(key: &str) -> Chain {
+    let chain_key = &key[21..];
+    match chain_key.strip_prefix("evm:") {
+        Some(chain_id) => Chain::Evm { chain_id: chain_id.parse().expect("malformed chain anchor key") },
+        None => Chain::Solana,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchor_keys_roundtrip() {
+        for chain in [Chain::Evm { chain_id: 137 }, Chain::Solana] {
+            let key = anchor_key(42, &chain);
+            assert_eq!((parse_key(&key), chain_of(&key)), (42, chain));
+        }
+        assert!(anchor_key(9, &Chain::Solana) < anchor_key(10, &Chain::Evm { chain_id: 1 }));
+    }
+}
@@ -0,0 +1,229 @@
+//! Canister configuration.
+//!
+//! `mode` decides whether `anchor()` talks to the BTC signer (`Live`) or
+//! fabricates a txid locally (`Mock`). It is fixed at install so a production
+//! deployment can never fall back to fake anchors. The remaining settings
+//! differ between local, staging and mainnet deployments and can be passed as
+//! install/upgrade arguments or changed later through `set_config`.
+//! `dedupe_receipts` makes `issue_receipt` reject a hash the same issuer has
+//! already committed to. `cross_chain_service` is asked whether a burn's
+//! message was attested by a quorum of its registered validators; burns are
+//! refused until it is set.
+//! `archive_policy` enables moving old confirmed batches to archive canisters.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+use crate::storage::{self, Memory};
+
+pub use proof_of_state_verifier::BtcNetwork;
+
+const DEFAULT_BTC_SIGNER: &str = "rdmx6-jaaaa-aaaaa-aaadq-cai";
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnchorMode {
+    #[default]
+    Live,
+    Mock,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchivePolicy {
+    // Confirmed batches older than this move to an archive
+    pub archive_after_secs: u64,
+    // A new archive is spawned once the current one holds this many batches
+    pub batches_per_archive: u64,
+    // Cycles each newly spawned archive is created with
+    pub archive_cycles: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CanisterConfig {
+    pub mode: AnchorMode,
+    pub btc_signer: Principal,
+    pub fee_rate: u64,
+    pub anchor_cycles: u64,
+    pub network: BtcNetwork,
+    pub dedupe_receipts: bool,
+    pub cross_chain_service: Option<Principal>,
+    pub archive_policy: Option<ArchivePolicy>,
+}
+
+impl Default for CanisterConfig {
+    fn default() -> Self {
+        CanisterConfig {
+            mode: AnchorMode::default(),
+            btc_signer: Principal::from_text(DEFAULT_BTC_SIGNER).unwrap(),
+            fee_rate: 1000,
+            anchor_cycles: 25_000_000_000,
+            network: BtcNetwork::default(),
+            dedupe_receipts: false,
+            cross_chain_service: None,
+            archive_policy: None,
+        }
+    }
+}
+
+// Passed on both install and upgrade; unset fields keep their current value.
+// The nested options distinguish "leave as is" (None) from "clear" (Some(None)).
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct InitArgs {
+    pub mode: Option<AnchorMode>,
+    pub btc_signer: Option<Principal>,
+    pub fee_rate: Option<u64>,
+    pub anchor_cycles: Option<u64>,
+    pub network: Option<BtcNetwork>,
+    pub dedupe_receipts: Option<bool>,
+    pub cross_chain_service: Option<Option<Principal>>,
+    pub archive_policy: Option<Option<ArchivePolicy>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct ConfigUpdate {
+    pub btc_signer: Option<Principal>,
+    pub fee_rate: Option<u64>,
+    pub anchor_cycles: Option<u64>,
+    pub network: Option<BtcNetwork>,
+    pub dedupe_receipts: Option<bool>,
+    pub cross_chain_service: Option<Option<Principal>>,
+    pub archive_policy: Option<Option<ArchivePolicy>>,
+}
+
+impl CanisterConfig {
+    fn apply(&self, update: ConfigUpdate) -> Result<CanisterConfig, String> {
+        let config = CanisterConfig {
+            mode: self.mode,
+            btc_signer: update.btc_signer.unwrap_or(self.btc_signer),
+            fee_rate: update.fee_rate.unwrap_or(self.fee_rate),
+            anchor_cycles: update.anchor_cycles.unwrap_or(self.anchor_cycles),
+            network: update.network.unwrap_or(self.network),
+            dedupe_receipts: update.dedupe_receipts.unwrap_or(self.dedupe_receipts),
+            cross_chain_service: update.cross_chain_service.unwrap_or(self.cross_chain_service),
+            archive_policy: update.archive_policy.unwrap_or_else(|| self.archive_policy.clone()),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.fee_rate == 0 {
+            return Err("fee_rate must be greater than zero".to_string());
+        }
+        if self.anchor_cycles == 0 {
+            return Err("anchor_cycles must be greater than zero".to_string());
+        }
+        if self.mode == AnchorMode::Mock && self.network == BtcNetwork::Mainnet {
+            return Err("Mock mode cannot be used on mainnet".to_string());
+        }
+        if let Some(policy) = &self.archive_policy {
+            if policy.batches_per_archive == 0 || policy.archive_cycles == 0 {
+                return Err("batches_per_archive and archive_cycles must be greater than zero".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl InitArgs {
+    fn into_update(self) -> ConfigUpdate {
+        ConfigUpdate {
+            btc_signer: self.btc_signer,
+            fee_rate: self.fee_rate,
+            anchor_cycles: self.anchor_cycles,
+            network: self.network,
+            dedupe_receipts: self.dedupe_receipts,
+            cross_chain_service: self.cross_chain_service,
+            archive_policy: self.archive_policy,
+        }
+    }
+}
+
+thread_local! {
+    static CONFIG: RefCell<StableCell<CanisterConfig, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::CONFIG_MEMORY), CanisterConfig::default())
+            .expect("failed to init canister config")
+    );
+}
+
+pub fn get() -> CanisterConfig {
+    CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn set(config: CanisterConfig) {
+    CONFIG.with(|c| c.borrow_mut().set(config).expect("failed to store canister config"));
+}
+
+pub fn mode() -> AnchorMode {
+    get().mode
+}
+
+pub fn apply_init_args(args: InitArgs) -> Result<(), String> {
+    let base = CanisterConfig { mode: args.mode.unwrap_or_default(), ..get() };
+    set(base.apply(args.into_update())?);
+    Ok(())
+}
+
+pub fn apply_upgrade_args(args: InitArgs) -> Result<(), String> {
+    let current = get();
+    if args.mode.is_some_and(|mode| mode != current.mode) {
+        return Err("Anchor mode is fixed at install and cannot change on upgrade".to_string());
+    }
+    set(current.apply(args.into_update())?);
+    Ok(())
+}
+
+pub fn update(update: ConfigUpdate) -> Result<CanisterConfig, String> {
+    let config = get().apply(update)?;
+    set(config.clone());
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_args_cannot_switch_mode() {
+        apply_init_args(InitArgs { mode: Some(AnchorMode::Mock), fee_rate: Some(5), ..Default::default() }).unwrap();
+        assert_eq!(get().fee_rate, 5);
+
+        let switch = InitArgs { mode: Some(AnchorMode::Live), ..Default::default() };
+        assert!(apply_upgrade_args(switch).is_err());
+
+        let retune = InitArgs { fee_rate: Some(7), ..Default::default() };
+        apply_upgrade_args(retune).unwrap();
+        assert_eq!(get().fee_rate, 7);
+        assert_eq!(get().mode, AnchorMode::Mock);
+    }
+
+    #[test]
+    fn mock_mode_is_rejected_on_mainnet() {
+        let args = InitArgs { mode: Some(AnchorMode::Mock), network: Some(BtcNetwork::Mainnet), ..Default::default() };
+        assert!(apply_init_args(args).is_err());
+    }
+
+    #[test]
+    fn optional_settings_can_be_cleared() {
+        let policy = ArchivePolicy { archive_after_secs: 60, batches_per_archive: 10, archive_cycles: 1 };
+        update(ConfigUpdate {
+            cross_chain_service: Some(Some(Principal::anonymous())),
+            archive_policy: Some(Some(policy.clone())),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let config = update(ConfigUpdate { fee_rate: Some(3), ..Default::default() }).unwrap();
+        assert_eq!(config.cross_chain_service, Some(Principal::anonymous()));
+        assert_eq!(config.archive_policy, Some(policy));
+
+        let config = update(ConfigUpdate {
+            cross_chain_service: Some(None),
+            archive_policy: Some(None),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(config.cross_chain_service, None);
+        assert_eq!(config.archive_policy, None);
+    }
+}
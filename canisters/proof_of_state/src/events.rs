@@ -0,0 +1,187 @@
+//! Append-only audit trail of changes to issued receipts.
+//!
+//! Every accepted state change (burns, revocations and supersessions) is
+//! appended as a `ReceiptEvent` with a fresh sequence number and never
+//! rewritten, except to record where it was committed. Pending events become
+//! leaves of the next batch after its receipts, so the change is covered by
+//! the same root, BTC anchor and signature. An event leaf is
+//!
+//! ```text
+//! H(0x00 || "iqube-event-v1" || seq_be || len-prefixed receipt_id, actor || timestamp_be || kind)
+//! ```
+//!
+//! where kind is `0x01 || len-prefixed message_id` for a burn,
+//! `0x02 || len-prefixed reason` for a revocation and
+//! `0x03 || len-prefixed new_receipt_id` for a supersession. Each of these ends
+//! the receipt's life, so a receipt has at most one event.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::merkle::{self, Hash};
+use crate::metadata::push_field;
+use crate::storage::{self, Memory};
+
+const LEAF_DOMAIN: &[u8] = b"iqube-event-v1";
+pub const MAX_REASON_LEN: usize = 256;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ReceiptEventKind {
+    Burned { message_id: String },
+    Revoked { reason: String },
+    Superseded { new_receipt_id: String },
+}
+
+impl ReceiptEventKind {
+    pub fn describe(&self) -> String {
+        match self {
+            ReceiptEventKind::Burned { .. } => "burned".to_string(),
+            ReceiptEventKind::Revoked { .. } => "revoked".to_string(),
+            ReceiptEventKind::Superseded { new_receipt_id } => format!("superseded by {}", new_receipt_id),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReceiptEvent {
+    pub seq: u64,
+    pub receipt_id: String,
+    pub kind: ReceiptEventKind,
+    pub actor: Principal,
+    pub timestamp: u64,
+    // Set once the event is committed in a batch
+    pub batch_root: Option<String>,
+    pub merkle_proof: Vec<String>,
+}
+
+thread_local! {
+    static EVENTS: RefCell<StableBTreeMap<u64, ReceiptEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::EVENTS_MEMORY)));
+    // Events awaiting a batch
+    static PENDING: RefCell<StableBTreeMap<u64, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::PENDING_EVENTS_MEMORY)));
+    // "<receipt_id>\0<seq:020>" -> seq, for a receipt's history in order
+    static BY_RECEIPT: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::RECEIPT_EVENTS_MEMORY)));
+}
+
+pub fn append(receipt_id: String, kind: ReceiptEventKind, actor: Principal, now: u64) -> ReceiptEvent {
+    let event = EVENTS.with(|e| {
+        let mut events = e.borrow_mut();
+        let seq = events.last_key_value().map(|(seq, _)| seq + 1).unwrap_or(0);
+        let event =
+            ReceiptEvent { seq, receipt_id, kind, actor, timestamp: now, batch_root: None, merkle_proof: vec![] };
+        events.insert(seq, event.clone());
+        event
+    });
+    PENDING.with(|p| p.borrow_mut().insert(event.seq, ()));
+    BY_RECEIPT.with(|b| b.borrow_mut().insert(format!("{}\0{:020}", event.receipt_id, event.seq), event.seq));
+    crate::icrc3::receipt_event(&event);
+    event
+}
+
+pub fn get(seq: u64) -> Option<ReceiptEvent> {
+    EVENTS.with(|e| e.borrow().get(&seq))
+}
+
+pub fn history(receipt_id: &str) -> Vec<ReceiptEvent> {
+    let prefix = format!("{}\0", receipt_id);
+    let seqs: Vec<u64> = BY_RECEIPT.with(|b| {
+        b.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, seq)| seq)
+            .collect()
+    });
+    seqs.into_iter().filter_map(get).collect()
+}
+
+/// The event that ended the receipt's life, if any.
+pub fn final_event(receipt_id: &str) -> Option<ReceiptEvent> {
+    history(receipt_id).into_iter().next()
+}
+
+pub fn pending_count() -> u64 {
+    PENDING.with(|p| p.borrow().len())
+}
+
+pub fn oldest_pending_at() -> Option<u64> {
+    let seq = PENDING.with(|p| p.borrow().first_key_value().map(|(seq, _)| seq))?;
+    get(seq).map(|event| event.timestamp)
+}
+
+/// Removes and returns every pending event, oldest first.
+pub fn take_pending() -> Vec<ReceiptEvent> {
+    let seqs: Vec<u64> = PENDING.with(|p| {
+        let mut pending = p.borrow_mut();
+        std::iter::from_fn(|| pending.pop_first().map(|(seq, _)| seq)).collect()
+    });
+    seqs.into_iter().filter_map(get).collect()
+}
+
+pub fn record_commitment(seq: u64, batch_root: &str, merkle_proof: Vec<String>) {
+    EVENTS.with(|e| {
+        let mut events = e.borrow_mut();
+        if let Some(mut event) = events.get(&seq) {
+            event.batch_root = Some(batch_root.to_string());
+            event.merkle_proof = merkle_proof;
+            events.insert(seq, event);
+        }
+    });
+}
+
+pub fn leaf_preimage(event: &ReceiptEvent) -> Vec<u8> {
+    let mut preimage = LEAF_DOMAIN.to_vec();
+    preimage.extend_from_slice(&event.seq.to_be_bytes());
+    push_field(&mut preimage, event.receipt_id.as_bytes());
+    push_field(&mut preimage, event.actor.as_slice());
+    preimage.extend_from_slice(&event.timestamp.to_be_bytes());
+    match &event.kind {
+        ReceiptEventKind::Burned { message_id } => {
+            preimage.push(0x01);
+            push_field(&mut preimage, message_id.as_bytes());
+        }
+        ReceiptEventKind::Revoked { reason } => {
+            preimage.push(0x02);
+            push_field(&mut preimage, reason.as_bytes());
+        }
+        ReceiptEventKind::Superseded { new_receipt_id } => {
+            preimage.push(0x03);
+            push_field(&mut preimage, new_receipt_id.as_bytes());
+        }
+    }
+    preimage
+}
+
+pub fn leaf(event: &ReceiptEvent) -> Hash {
+    merkle::leaf_hash(&leaf_preimage(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_is_per_receipt_and_in_order() {
+        let burn = |id: &str, message: &str| {
+            append(id.to_string(), ReceiptEventKind::Burned { message_id: message.to_string() }, Principal::anonymous(), 1)
+        };
+        let first = burn("receipt_1", "m1");
+        burn("receipt_10", "m2");
+        let third = burn("receipt_1", "m3");
+
+        assert_eq!(history("receipt_1"), vec![first.clone(), third.clone()]);
+        assert_eq!(take_pending().len(), 3);
+        assert_eq!(pending_count(), 0);
+        assert_eq!(final_event("receipt_1"), Some(first.clone()));
+        assert_eq!(final_event("receipt_2"), None);
+        assert_ne!(leaf(&first), leaf(&ReceiptEvent { actor: Principal::management_canister(), ..first.clone() }));
+        // Kinds with the same payload still commit to different leaves
+        let revoked = ReceiptEvent { kind: ReceiptEventKind::Revoked { reason: "m1".to_string() }, ..first.clone() };
+        let superseded =
+            ReceiptEvent { kind: ReceiptEventKind::Superseded { new_receipt_id: "m1".to_string() }, ..first.clone() };
+        assert_ne!(leaf(&first), leaf(&revoked));
+        assert_ne!(leaf(&revoked), leaf(&superseded));
+    }
+}
@@ -0,0 +1,387 @@
+//! HTTP verification gateway, so a plain link can show a receipt's status.
+//!
+//! ```text
+//! GET /receipt/<id>[.json]   verification result
+//! GET /batch/<root>[.json]   batch summary and anchors
+//! GET /proof/<id>.ots        OpenTimestamps proof download
+//! GET /proof/<id>.json       proof bundle download, also as .cbor
+//! ```
+//!
+//! Receipt and batch pages are HTML when the client accepts `text/html` and
+//! JSON otherwise or with a `.json` suffix. Query responses carry no
+//! certification, so `http_request` answers every known route with `upgrade`
+//! and the HTTP gateway replays it as `http_request_update`, whose response
+//! goes through consensus. Each page view therefore costs an update call, but
+//! the browser can trust what it shows without an SDK, and archived receipts
+//! can be fetched from their archive.
+
+use candid::{CandidType, Deserialize};
+use serde_json::json;
+
+use crate::anchoring::AnchorStatus;
+use crate::config::AnchorMode;
+use crate::events::ReceiptEventKind;
+use crate::{BatchSummary, Receipt, ReceiptVerification};
+
+const MAX_ID_LEN: usize = 128;
+
+pub type HeaderField = (String, String);
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+    pub certificate_version: Option<u16>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct HttpUpdateRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+    pub upgrade: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Route {
+    Receipt { id: String, format: Format },
+    Batch { root: String, format: Format },
+    Ots { id: String },
+    Bundle { id: String, cbor: bool },
+}
+
+fn response(status_code: u16, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        body,
+        upgrade: None,
+    }
+}
+
+pub fn text(status_code: u16, message: &str) -> HttpResponse {
+    response(status_code, "text/plain; charset=utf-8", message.as_bytes().to_vec())
+}
+
+pub fn upgrade() -> HttpResponse {
+    HttpResponse { status_code: 200, headers: vec![], body: vec![], upgrade: Some(true) }
+}
+
+pub fn download(file_name: &str, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    let mut download = response(200, content_type, body);
+    download
+        .headers
+        .push(("Content-Disposition".to_string(), format!("attachment; filename=\"{}\"", file_name)));
+    download
+}
+
+// IDs and roots end up in headers and markup, so only plain ASCII is routed
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_ID_LEN && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+fn wants_html(headers: &[HeaderField]) -> bool {
+    headers
+        .iter()
+        .any(|(name, value)| name.eq_ignore_ascii_case("accept") && value.contains("text/html"))
+}
+
+/// Maps a request to a route, or to the error response to send instead.
+pub fn route(method: &str, url: &str, headers: &[HeaderField]) -> Result<Route, HttpResponse> {
+    if method != "GET" {
+        let mut not_allowed = text(405, "Only GET is supported");
+        not_allowed.headers.push(("Allow".to_string(), "GET".to_string()));
+        return Err(not_allowed);
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let (kind, name) = path.trim_start_matches('/').split_once('/').unwrap_or(("", ""));
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) => (stem, Some(extension)),
+        None => (name, None),
+    };
+    if !valid_id(stem) {
+        return Err(text(404, "Not found"));
+    }
+    let format = match extension {
+        Some("json") => Format::Json,
+        _ if wants_html(headers) => Format::Html,
+        _ => Format::Json,
+    };
+    let stem = stem.to_string();
+    match (kind, extension) {
+        ("receipt", None | Some("json")) => Ok(Route::Receipt { id: stem, format }),
+        ("batch", None | Some("json")) => Ok(Route::Batch { root: stem, format }),
+        ("proof", Some("ots")) => Ok(Route::Ots { id: stem }),
+        ("proof", Some("json")) => Ok(Route::Bundle { id: stem, cbor: false }),
+        ("proof", Some("cbor")) => Ok(Route::Bundle { id: stem, cbor: true }),
+        _ => Err(text(404, "Not found")),
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// IC time in nanoseconds as "YYYY-MM-DD HH:MM:SS UTC"
+fn utc(nanos: u64) -> String {
+    let secs = nanos / 1_000_000_000;
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // Days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+fn anchor_state(status: &AnchorStatus) -> &'static str {
+    match status {
+        AnchorStatus::Unanchored => "unanchored",
+        AnchorStatus::Broadcast { .. } => "broadcast",
+        AnchorStatus::Confirmed { .. } => "confirmed",
+        AnchorStatus::Failed { .. } => "failed",
+    }
+}
+
+fn describe_anchor(status: &AnchorStatus, mode: Option<AnchorMode>, is_final: bool) -> String {
+    let mut description = match (status.txid(), status.block_height()) {
+        (Some(txid), Some(height)) => format!("BTC transaction {} in block {}", txid, height),
+        (Some(txid), None) => format!("BTC transaction {}, not mined yet", txid),
+        (None, _) => format!("not anchored ({})", anchor_state(status)),
+    };
+    if is_final {
+        description.push_str(", final");
+    }
+    if mode == Some(AnchorMode::Mock) {
+        description.push_str(" (mock anchor, test deployment)");
+    }
+    description
+}
+
+// Machine-readable status and a sentence for people
+fn receipt_status(verification: &ReceiptVerification) -> (&'static str, String) {
+    if let Some(event) = &verification.final_event {
+        let status = match event.kind {
+            ReceiptEventKind::Burned { .. } => "burned",
+            ReceiptEventKind::Revoked { .. } => "revoked",
+            ReceiptEventKind::Superseded { .. } => "superseded",
+        };
+        return (status, format!("This receipt was {} on {}.", event.kind.describe(), utc(event.timestamp)));
+    }
+    match (verification.root_matches_batch, verification.anchor_final) {
+        (false, _) => ("pending", "This receipt is issued and waiting to be committed in a batch.".to_string()),
+        (true, false) => ("committed", "This receipt is committed in a batch whose Bitcoin anchor is not final yet.".to_string()),
+        (true, true) => ("verified", "This receipt is committed in a batch anchored in Bitcoin at final depth.".to_string()),
+    }
+}
+
+fn page(title: &str, summary: &str, rows: &[(&str, String)], links: &[(&str, String)]) -> Vec<u8> {
+    let rows: String =
+        rows.iter().map(|(label, value)| format!("<dt>{}</dt><dd>{}</dd>", escape(label), escape(value))).collect();
+    let links: Vec<String> =
+        links.iter().map(|(label, href)| format!("<a href=\"{}\">{}</a>", escape(href), escape(label))).collect();
+    format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{title}</title><style>body{{font-family:system-ui,sans-serif;max-width:46rem;margin:2rem auto;\
+         padding:0 1rem}}dt{{font-weight:600}}dd{{margin:0 0 .75rem;word-break:break-all}}</style></head>\
+         <body><h1>{title}</h1><p>{summary}</p><dl>{rows}</dl><p>{links}</p></body></html>",
+        title = escape(title),
+        summary = escape(summary),
+        rows = rows,
+        links = links.join(" &middot; "),
+    )
+    .into_bytes()
+}
+
+fn json_response(value: serde_json::Value) -> HttpResponse {
+    let body = serde_json::to_vec_pretty(&value).expect("JSON values always serialize");
+    response(200, "application/json", body)
+}
+
+pub fn receipt(receipt: &Receipt, verification: &ReceiptVerification, format: Format) -> HttpResponse {
+    let (status, summary) = receipt_status(verification);
+    let batch_root = verification.root_matches_batch.then(|| verification.computed_root.clone());
+    let ots = format!("/proof/{}.ots", receipt.id);
+    let bundle = format!("/proof/{}.json", receipt.id);
+    match format {
+        Format::Json => json_response(json!({
+            "status": status,
+            "summary": summary,
+            "receipt": receipt,
+            "leaf_hash": verification.leaf_hash,
+            "batch_root": batch_root,
+            "anchor": verification.anchor_status.as_ref().map(|anchor| json!({
+                "state": anchor_state(anchor),
+                "txid": anchor.txid(),
+                "block_height": anchor.block_height(),
+                "final": verification.anchor_final,
+                "mock": verification.anchor_mode == Some(AnchorMode::Mock),
+            })),
+            "final_event": verification.final_event.as_ref().map(|event| json!({
+                "seq": event.seq,
+                "description": event.kind.describe(),
+                "timestamp": event.timestamp,
+                "batch_root": event.batch_root,
+            })),
+            "links": { "ots": ots, "bundle": bundle },
+        })),
+        Format::Html => {
+            let mut rows = vec![
+                ("Receipt", receipt.id.clone()),
+                ("Data hash", receipt.data_hash.clone()),
+                ("Issued", utc(receipt.timestamp)),
+            ];
+            if let Some(iqube_id) = receipt.metadata.as_ref().and_then(|m| m.iqube_id.clone()) {
+                rows.push(("iQube", iqube_id));
+            }
+            let mut links = vec![];
+            if let Some(root) = &batch_root {
+                rows.push(("Batch", root.clone()));
+                links.push(("Batch", format!("/batch/{}", root)));
+            }
+            if let Some(anchor) = &verification.anchor_status {
+                rows.push(("Anchor", describe_anchor(anchor, verification.anchor_mode, verification.anchor_final)));
+            }
+            if verification.anchor_final {
+                links.push(("OpenTimestamps proof", ots));
+            }
+            if batch_root.is_some() {
+                links.push(("Proof bundle", bundle));
+            }
+            links.push(("JSON", format!("/receipt/{}.json", receipt.id)));
+            let title = format!("Receipt {}", status);
+            response(200, "text/html; charset=utf-8", page(&title, &summary, &rows, &links))
+        }
+    }
+}
+
+/// `archive` is the archive canister now holding the batch, if any.
+pub fn batch(batch: &BatchSummary, anchor_final: bool, archive: Option<candid::Principal>, format: Format) -> HttpResponse {
+    let chain_anchors: Vec<(String, &AnchorStatus)> = batch
+        .chain_anchors
+        .iter()
+        .map(|anchor| (anchor.chain.key(), &anchor.status))
+        .collect();
+    match format {
+        Format::Json => json_response(json!({
+            "seq": batch.seq,
+            "root": batch.root,
+            "receipt_count": batch.receipt_count,
+            "created_at": batch.created_at,
+            "mmr_size": batch.mmr_size,
+            "signed": batch.signature.is_some(),
+            "anchor": {
+                "state": anchor_state(&batch.anchor_status),
+                "txid": batch.anchor_status.txid(),
+                "block_height": batch.anchor_status.block_height(),
+                "final": anchor_final,
+                "mock": batch.anchor_mode == Some(AnchorMode::Mock),
+            },
+            "chain_anchors": chain_anchors.iter().map(|(chain, status)| json!({
+                "chain": chain,
+                "state": anchor_state(status),
+                "txid": status.txid(),
+            })).collect::<Vec<_>>(),
+            "archive": archive.map(|archive| archive.to_text()),
+        })),
+        Format::Html => {
+            let mut rows = vec![
+                ("Root", batch.root.clone()),
+                ("Sequence", batch.seq.to_string()),
+                ("Receipts", batch.receipt_count.to_string()),
+                ("Created", utc(batch.created_at)),
+                ("Bitcoin anchor", describe_anchor(&batch.anchor_status, batch.anchor_mode, anchor_final)),
+                ("Signed", if batch.signature.is_some() { "yes" } else { "not yet" }.to_string()),
+            ];
+            for (chain, status) in &chain_anchors {
+                let state = match status.txid() {
+                    Some(txid) => format!("{} ({})", txid, anchor_state(status)),
+                    None => anchor_state(status).to_string(),
+                };
+                rows.push(("Anchor", format!("{}: {}", chain, state)));
+            }
+            if let Some(archive) = archive {
+                rows.push(("Archived in", archive.to_text()));
+            }
+            let summary = if anchor_final {
+                "This batch is anchored in Bitcoin at final depth."
+            } else {
+                "This batch's Bitcoin anchor is not final yet."
+            };
+            let links = [("JSON", format!("/batch/{}.json", batch.root))];
+            response(200, "text/html; charset=utf-8", page("Batch", summary, &rows, &links))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_html() -> Vec<HeaderField> {
+        vec![("Accept".to_string(), "text/html,application/xhtml+xml".to_string())]
+    }
+
+    #[test]
+    fn routes_and_formats() {
+        let html = accept_html();
+        assert_eq!(
+            route("GET", "/receipt/receipt_1_ab?ref=mail", &html),
+            Ok(Route::Receipt { id: "receipt_1_ab".to_string(), format: Format::Html })
+        );
+        assert_eq!(
+            route("GET", "/receipt/receipt_1_ab.json", &html),
+            Ok(Route::Receipt { id: "receipt_1_ab".to_string(), format: Format::Json })
+        );
+        assert_eq!(route("GET", "/batch/abcd", &[]), Ok(Route::Batch { root: "abcd".to_string(), format: Format::Json }));
+        assert_eq!(route("GET", "/proof/r.ots", &[]), Ok(Route::Ots { id: "r".to_string() }));
+        assert_eq!(route("GET", "/proof/r.cbor", &[]), Ok(Route::Bundle { id: "r".to_string(), cbor: true }));
+
+        assert_eq!(route("POST", "/receipt/r", &[]).unwrap_err().status_code, 405);
+        for url in ["/", "/proof/r", "/receipt/r.xml", "/receipt/a\"b", "/other/r", "/receipt/"] {
+            assert_eq!(route("GET", url, &[]).unwrap_err().status_code, 404, "{}", url);
+        }
+    }
+
+    #[test]
+    fn pages_escape_their_content() {
+        let body = String::from_utf8(page("<t>", "a & b", &[("k", "<script>".to_string())], &[])).unwrap();
+        assert!(body.contains("&lt;t&gt;") && body.contains("a &amp; b") && body.contains("&lt;script&gt;"));
+        assert!(!body.contains("<script>"));
+        assert_eq!(utc(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(utc(1_709_251_199 * 1_000_000_000), "2024-02-29 23:59:59 UTC");
+    }
+}
@@ -0,0 +1,326 @@
+//! ICRC-3 block log of every state change.
+//!
+//! Each issued receipt, batch cut, anchor broadcast, anchor reaching final
+//! depth and receipt event (burn, revocation, supersession) is appended as a
+//! block
+//!
+//! ```text
+//! { btype : text; ts : nat; phash : blob; tx : map }
+//! ```
+//!
+//! where `phash` is the ICRC-3 representation-independent hash of the
+//! previous block (absent on block 0), so the log is a hash chain. The newest
+//! block's index and hash are part of the certified tree, which lets indexers
+//! sync the canonical history with `icrc3_get_blocks` and check its tip with
+//! `icrc3_get_tip_certificate`. Hashes and principals are blobs, receipt IDs
+//! and txids are text. Blocks are never archived, so `icrc3_get_archives` is
+//! empty and every block is served by this canister.
+
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::certification;
+use crate::chains::Chain;
+use crate::events::{ReceiptEvent, ReceiptEventKind};
+use crate::merkle::Hash;
+use crate::storage::{self, Memory};
+
+// Most blocks returned by one icrc3_get_blocks call
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 100;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Value,
+}
+
+candid::define_function!(pub GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksCallback,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetArchivesArgs {
+    pub from: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchiveRange {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DataCertificate {
+    pub certificate: Vec<u8>,
+    pub hash_tree: Vec<u8>,
+}
+
+thread_local! {
+    static BLOCKS: RefCell<StableBTreeMap<u64, Value, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::BLOCKS_MEMORY)));
+}
+
+fn sha256(bytes: &[u8]) -> Hash {
+    Sha256::digest(bytes).into()
+}
+
+impl Value {
+    /// The ICRC-3 representation-independent hash.
+    pub fn hash(&self) -> Hash {
+        match self {
+            Value::Blob(bytes) => sha256(bytes),
+            Value::Text(text) => sha256(text.as_bytes()),
+            Value::Nat(nat) => {
+                let mut leb = Vec::new();
+                nat.encode(&mut leb).expect("writing to a Vec cannot fail");
+                sha256(&leb)
+            }
+            Value::Int(int) => {
+                let mut sleb = Vec::new();
+                int.encode(&mut sleb).expect("writing to a Vec cannot fail");
+                sha256(&sleb)
+            }
+            Value::Array(values) => sha256(&values.iter().flat_map(Value::hash).collect::<Vec<u8>>()),
+            Value::Map(entries) => {
+                let mut pairs: Vec<Vec<u8>> =
+                    entries.iter().map(|(key, value)| [sha256(key.as_bytes()), value.hash()].concat()).collect();
+                pairs.sort();
+                sha256(&pairs.concat())
+            }
+        }
+    }
+}
+
+fn nat(value: u64) -> Value {
+    Value::Nat(Nat::from(value))
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+fn blob(value: &[u8]) -> Value {
+    Value::Blob(value.to_vec())
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Map(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+// Hex roots are stored as their 32 bytes
+fn root(root: &str) -> Value {
+    Value::Blob(hex::decode(root).expect("stored batch root is not hex"))
+}
+
+fn chain_name(chain: Option<&Chain>) -> String {
+    chain.map(Chain::key).unwrap_or_else(|| "btc".to_string())
+}
+
+/// Appends a block, moves the certified tip to it and returns its index.
+fn append(btype: &str, tx: Value, now: u64) -> u64 {
+    let (index, hash) = BLOCKS.with(|b| {
+        let mut blocks = b.borrow_mut();
+        let parent = blocks.last_key_value();
+        let index = parent.as_ref().map(|(index, _)| index + 1).unwrap_or(0);
+        let mut fields = vec![("btype", text(btype)), ("ts", nat(now))];
+        if let Some((_, parent)) = parent {
+            fields.push(("phash", blob(&parent.hash())));
+        }
+        fields.push(("tx", tx));
+        let block = map(fields);
+        let hash = block.hash();
+        blocks.insert(index, block);
+        (index, hash)
+    });
+    certification::set_tip(index, hash);
+    certification::commit();
+    index
+}
+
+pub fn receipt_issued(receipt: &crate::Receipt, leaf: &Hash) -> u64 {
+    let mut tx = vec![
+        ("id", text(&receipt.id)),
+        ("data_hash", text(&receipt.data_hash)),
+        ("leaf", blob(leaf)),
+        ("issuer", blob(receipt.issuer.as_slice())),
+    ];
+    if let Some(iqube_id) = receipt.metadata.as_ref().and_then(|m| m.iqube_id.as_deref()) {
+        tx.push(("iqube_id", text(iqube_id)));
+    }
+    append("iqube.receipt", map(tx), receipt.timestamp)
+}
+
+pub fn batch_cut(batch: &crate::MerkleBatch, seq: u64) -> u64 {
+    let receipts = Value::Array(batch.receipts.iter().map(|receipt| text(&receipt.id)).collect());
+    let events = Value::Array(batch.events.iter().map(|seq| nat(*seq)).collect());
+    let tx = vec![
+        ("seq", nat(seq)),
+        ("root", root(&batch.root)),
+        ("receipts", receipts),
+        ("events", events),
+        ("mmr_size", nat(batch.mmr_size)),
+    ];
+    append("iqube.batch", map(tx), batch.created_at)
+}
+
+fn anchor_tx(seq: u64, batch_root: &str, chain: Option<&Chain>, txid: &str) -> Value {
+    map(vec![
+        ("seq", nat(seq)),
+        ("root", root(batch_root)),
+        ("chain", Value::Text(chain_name(chain))),
+        ("txid", text(txid)),
+    ])
+}
+
+/// `chain` is None for the BTC anchor.
+pub fn anchor_broadcast(seq: u64, batch_root: &str, chain: Option<&Chain>, txid: &str, now: u64) -> u64 {
+    append("iqube.anchor", anchor_tx(seq, batch_root, chain, txid), now)
+}
+
+pub fn anchor_confirmed(seq: u64, batch_root: &str, chain: Option<&Chain>, txid: &str, now: u64) -> u64 {
+    append("iqube.anchor_confirmed", anchor_tx(seq, batch_root, chain, txid), now)
+}
+
+pub fn receipt_event(event: &ReceiptEvent) -> u64 {
+    let mut tx = vec![
+        ("event_seq", nat(event.seq)),
+        ("receipt_id", text(&event.receipt_id)),
+        ("actor", blob(event.actor.as_slice())),
+    ];
+    let btype = match &event.kind {
+        ReceiptEventKind::Burned { message_id } => {
+            tx.push(("message_id", text(message_id)));
+            "iqube.burn"
+        }
+        ReceiptEventKind::Revoked { reason } => {
+            tx.push(("reason", text(reason)));
+            "iqube.revoke"
+        }
+        ReceiptEventKind::Superseded { new_receipt_id } => {
+            tx.push(("new_receipt_id", text(new_receipt_id)));
+            "iqube.supersede"
+        }
+    };
+    append(btype, map(tx), event.timestamp)
+}
+
+pub fn log_length() -> u64 {
+    BLOCKS.with(|b| b.borrow().len())
+}
+
+/// Index and hash of the newest block.
+pub fn tip() -> Option<(u64, Hash)> {
+    BLOCKS.with(|b| b.borrow().last_key_value()).map(|(index, block)| (index, block.hash()))
+}
+
+// Out-of-range values clamp to u64::MAX, which is past the end of any log
+fn to_u64(value: &Nat) -> u64 {
+    u64::try_from(value.0.clone()).unwrap_or(u64::MAX)
+}
+
+/// Serves every requested range, at most `MAX_BLOCKS_PER_REQUEST` blocks in
+/// total; ranges past the end of the log return what exists.
+pub fn get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let mut budget = MAX_BLOCKS_PER_REQUEST;
+    let mut blocks = Vec::new();
+    BLOCKS.with(|b| {
+        let stored = b.borrow();
+        for range in args {
+            let start = to_u64(&range.start);
+            let length = to_u64(&range.length).min(budget);
+            for (index, block) in stored.range(start..start.saturating_add(length)) {
+                blocks.push(BlockWithId { id: Nat::from(index), block });
+                budget -= 1;
+            }
+        }
+    });
+    GetBlocksResult { log_length: Nat::from(log_length()), blocks, archived_blocks: vec![] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_hash(value: &Value) -> String {
+        hex::encode(value.hash())
+    }
+
+    #[test]
+    fn values_hash_like_the_icrc3_examples() {
+        assert_eq!(hex_hash(&nat(42)), "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1");
+        assert_eq!(
+            hex_hash(&Value::Int(Int::from(-42))),
+            "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc"
+        );
+        assert_eq!(hex_hash(&text("Hello, World!")), "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f");
+        assert_eq!(hex_hash(&blob(&[1, 2, 3, 4])), "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a");
+        assert_eq!(
+            hex_hash(&Value::Array(vec![nat(3), text("foo"), blob(&[5, 6])])),
+            "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6"
+        );
+        // Map hashes do not depend on entry order
+        let forward = map(vec![("a", nat(1)), ("b", text("x"))]);
+        let backward = map(vec![("b", text("x")), ("a", nat(1))]);
+        assert_eq!(forward.hash(), backward.hash());
+    }
+
+    #[test]
+    fn blocks_form_a_hash_chain_with_a_certified_tip() {
+        assert!(tip().is_none());
+        let root = "ab".repeat(32);
+        let first = anchor_broadcast(0, &root, None, "tx_1", 1);
+        let second = anchor_confirmed(0, &root, Some(&Chain::Solana), "tx_2", 2);
+        assert_eq!((first, second), (0, 1));
+
+        let result = get_blocks(vec![GetBlocksArgs { start: Nat::from(0u64), length: Nat::from(10u64) }]);
+        assert_eq!(result.log_length, Nat::from(2u64));
+        assert!(result.archived_blocks.is_empty());
+        let Value::Map(fields) = &result.blocks[1].block else { panic!("block is not a map") };
+        let phash = fields.iter().find(|(key, _)| key == "phash").map(|(_, value)| value);
+        assert_eq!(phash, Some(&blob(&result.blocks[0].block.hash())));
+        let Value::Map(fields) = &result.blocks[0].block else { panic!("block is not a map") };
+        assert!(fields.iter().all(|(key, _)| key != "phash"));
+        assert_eq!(tip(), Some((1, result.blocks[1].block.hash())));
+        assert!(certification::tip_witness().is_some());
+
+        // Requests are capped in total, and out-of-range starts are empty
+        for seq in 0..MAX_BLOCKS_PER_REQUEST {
+            anchor_broadcast(seq, &root, None, "tx", 3);
+        }
+        let all = GetBlocksArgs { start: Nat::from(0u64), length: Nat::from(u64::MAX) };
+        let again = GetBlocksArgs { start: Nat::from(0u64), length: Nat::from(5u64) };
+        assert_eq!(get_blocks(vec![all, again]).blocks.len() as u64, MAX_BLOCKS_PER_REQUEST);
+        let past = GetBlocksArgs { start: Nat::from(1_000u64), length: Nat::from(5u64) };
+        assert!(get_blocks(vec![past]).blocks.is_empty());
+    }
+}
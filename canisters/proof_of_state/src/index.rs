@@ -0,0 +1,76 @@
+//! Secondary receipt indexes by iQube ID, issuer and data hash.
+//!
+//! Each index is a stable map keyed `"<value>\0<timestamp:020>\0<receipt_id>"`,
+//! so a prefix scan returns a value's receipts in issue order.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::storage::{self, Memory};
+use crate::Receipt;
+
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+type Index = StableBTreeMap<String, String, Memory>;
+
+// A page of a listing. Receipts whose batch moved to an archive are not held
+// here any more and come back as `archived` so the caller can fetch them there.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ReceiptPage {
+    pub receipts: Vec<Receipt>,
+    pub archived: Vec<ArchivedReceipt>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchivedReceipt {
+    pub receipt_id: String,
+    pub archive: Principal,
+}
+
+thread_local! {
+    static BY_IQUBE: RefCell<Index> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::IQUBE_INDEX_MEMORY)));
+    static BY_ISSUER: RefCell<Index> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ISSUER_INDEX_MEMORY)));
+    static BY_DATA_HASH: RefCell<Index> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::DATA_HASH_INDEX_MEMORY)));
+}
+
+fn key(value: &str, receipt: &Receipt) -> String {
+    format!("{}\0{:020}\0{}", value, receipt.timestamp, receipt.id)
+}
+
+pub fn index_receipt(receipt: &Receipt) {
+    if let Some(iqube_id) = receipt.metadata.as_ref().and_then(|m| m.iqube_id.as_ref()) {
+        BY_IQUBE.with(|i| i.borrow_mut().insert(key(iqube_id, receipt), receipt.id.clone()));
+    }
+    BY_ISSUER.with(|i| i.borrow_mut().insert(key(&receipt.issuer.to_text(), receipt), receipt.id.clone()));
+    BY_DATA_HASH.with(|i| i.borrow_mut().insert(key(&receipt.data_hash, receipt), receipt.id.clone()));
+}
+
+fn scan(index: &Index, value: &str, offset: u64, limit: u64) -> Vec<String> {
+    let prefix = format!("{}\0", value);
+    index
+        .range(prefix.clone()..)
+        .take_while(|(k, _)| k.starts_with(&prefix))
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|(_, receipt_id)| receipt_id)
+        .collect()
+}
+
+pub fn by_iqube(iqube_id: &str, offset: u64, limit: u64) -> Vec<String> {
+    BY_IQUBE.with(|i| scan(&i.borrow(), iqube_id, offset, limit))
+}
+
+pub fn by_issuer(issuer: &Principal, offset: u64, limit: u64) -> Vec<String> {
+    BY_ISSUER.with(|i| scan(&i.borrow(), &issuer.to_text(), offset, limit))
+}
+
+// Validated hashes are stored as lowercase hex without a prefix
+pub fn by_data_hash(data_hash: &str, offset: u64, limit: u64) -> Vec<String> {
+    let data_hash = data_hash.trim();
+    let normalized = data_hash.strip_prefix("0x").unwrap_or(data_hash).to_lowercase();
+    BY_DATA_HASH.with(|i| scan(&i.borrow(), &normalized, offset, limit))
+}
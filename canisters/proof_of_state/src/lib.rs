@@ -1,162 +1,1278 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::{query, update};
-use std::cell::RefCell;
-use std::collections::HashMap;
-use sha2::{Sha256, Digest};
+use ic_cdk::{init, post_upgrade, query, update};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
 
-#[derive(CandidType, Deserialize, Clone)]
-pub struct Receipt {
-    pub id: String,
-    pub data_hash: String,
-    pub timestamp: u64,
-    pub merkle_proof: Vec<String>,
-}
+mod access;
+mod anchoring;
+mod archive;
+mod certification;
+mod chains;
+mod config;
+mod events;
+mod http;
+mod icrc3;
+mod index;
+mod mmr;
+mod notifications;
+mod scheduler;
+mod signing;
+mod storage;
+
+use proof_of_state_verifier::{bundle, data_hash, merkle, metadata, ots, receipt_leaf, Receipt};
+
+use access::{Role, RoleGrant};
+use anchoring::{AnchorProof, AnchorQueueEntry, AnchorRetry, AnchorStatus, TransactionStatus};
+use archive::{ArchiveInfo, BatchLocation};
+use bundle::{ExportedBundle, ProofBundle};
+use certification::{CertifiedBatch, CertifiedReceipt};
+use chains::{AnchorTarget, Chain, ChainAnchor};
+use config::{AnchorMode, CanisterConfig, ConfigUpdate, InitArgs};
+use data_hash::{DataHash, IssueError};
+use events::{ReceiptEvent, ReceiptEventKind};
+use http::{HttpRequest, HttpResponse, HttpUpdateRequest, Route};
+use icrc3::{ArchiveRange, DataCertificate, GetArchivesArgs, GetBlocksArgs, GetBlocksResult};
+use index::{ArchivedReceipt, ReceiptPage};
+use merkle::MerkleTree;
+use metadata::ReceiptMetadata;
+use mmr::{MmrConsistencyProof, MmrInclusionProof, MmrState};
+use notifications::{Notification, NotificationKind, QueuedDelivery, Subscription};
+use scheduler::{BatchPolicy, SchedulerStatus};
+use signing::{BatchSignature, SigningKey};
+use sha2::{Digest, Sha256};
+use storage::Memory;
 
 #[derive(CandidType, Deserialize, Clone)]
 pub struct MerkleBatch {
     pub root: String,
     pub receipts: Vec<Receipt>,
     pub created_at: u64,
-    pub btc_anchor_txid: Option<String>,
-    pub btc_block_height: Option<u64>,
+    pub anchor_status: AnchorStatus,
+    // Set on first broadcast; Mock means the txid was fabricated locally
+    pub anchor_mode: Option<AnchorMode>,
+    // MMR size once this batch was appended; the anchor commits to that MMR root
+    pub mmr_size: u64,
+    // Threshold ECDSA attestation of the root, see `signing`; absent until signed
+    pub signature: Option<BatchSignature>,
+    // Anchors on the non-BTC targets enabled when the batch was cut, see `chains`
+    pub chain_anchors: Vec<ChainAnchor>,
+    // Sequence numbers of the receipt events committed after the receipts
+    pub events: Vec<u64>,
+}
+
+// A batch without its inlined receipts, for listings
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BatchSummary {
+    pub seq: u64,
+    pub root: String,
+    pub receipt_count: u64,
+    pub created_at: u64,
+    pub anchor_status: AnchorStatus,
+    pub anchor_mode: Option<AnchorMode>,
+    pub mmr_size: u64,
+    pub signature: Option<BatchSignature>,
+    pub chain_anchors: Vec<ChainAnchor>,
+}
+
+impl MerkleBatch {
+    fn summary(&self, seq: u64) -> BatchSummary {
+        BatchSummary {
+            seq,
+            root: self.root.clone(),
+            receipt_count: self.receipts.len() as u64,
+            created_at: self.created_at,
+            anchor_status: self.anchor_status.clone(),
+            anchor_mode: self.anchor_mode,
+            mmr_size: self.mmr_size,
+            signature: self.signature.clone(),
+            chain_anchors: self.chain_anchors.clone(),
+        }
+    }
+
+    // Path from this batch's root to the MMR root its anchor commits to
+    fn mmr_proof(&self, seq: u64) -> Result<MmrInclusionProof, String> {
+        mmr::inclusion_proof(seq, self.mmr_size)
+    }
+
+    // Hex of the 32 bytes the anchor transaction's OP_RETURN carries
+    fn anchor_commitment(&self) -> Result<String, String> {
+        Ok(hex::encode(mmr::root_at(self.mmr_size)?))
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ReceiptVerification {
+    pub receipt_id: String,
+    pub leaf_hash: String,
+    pub computed_root: String,
+    pub root_matches_batch: bool,
+    pub anchor_status: Option<AnchorStatus>,
+    pub anchor_mode: Option<AnchorMode>,
+    pub anchor_final: bool,
+    // The burn, revocation or supersession that ended the receipt, if any
+    pub final_event: Option<ReceiptEvent>,
 }
 
 thread_local! {
-    static RECEIPTS: RefCell<HashMap<String, Receipt>> = RefCell::new(HashMap::new());
-    static BATCHES: RefCell<Vec<MerkleBatch>> = RefCell::new(Vec::new());
-    static PENDING_RECEIPTS: RefCell<Vec<Receipt>> = RefCell::new(Vec::new());
-    static BURN_STATES: RefCell<HashMap<String, BurnState>> = RefCell::new(HashMap::new());
+    static RECEIPTS: RefCell<StableBTreeMap<String, Receipt, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::RECEIPTS_MEMORY)));
+    // Keyed by batch sequence number, so iteration order is creation order
+    static BATCHES: RefCell<StableBTreeMap<u64, MerkleBatch, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::BATCHES_MEMORY)));
+    // Receipt IDs awaiting a batch, keyed by issue sequence number
+    static PENDING_RECEIPTS: RefCell<StableBTreeMap<u64, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::PENDING_MEMORY)));
+    // Number of receipts issued so far; feeds receipt ID derivation
+    static RECEIPT_COUNTER: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::RECEIPT_COUNTER_MEMORY), 0)
+            .expect("failed to init receipt counter")
+    );
+    // "<issuer>:<data_hash>" -> receipt ID, for per-issuer dedupe
+    static ISSUER_HASHES: RefCell<StableBTreeMap<String, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ISSUER_HASHES_MEMORY)));
+    static BURN_STATES: RefCell<StableBTreeMap<String, BurnState, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::BURN_STATES_MEMORY)));
+    // Cross-chain message ID -> the receipt its burn consumed
+    static BURN_MESSAGES: RefCell<StableBTreeMap<String, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::BURN_MESSAGES_MEMORY)));
+    static BATCH_ROOTS: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::BATCH_ROOTS_MEMORY)));
+    // Receipt ID -> sequence number of the batch that includes it
+    static RECEIPT_BATCH: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::RECEIPT_BATCH_MEMORY)));
+    // Batches still waiting for a successful broadcast, with their retry schedule
+    static ANCHOR_QUEUE: RefCell<StableBTreeMap<u64, AnchorRetry, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ANCHOR_QUEUE_MEMORY)));
+    static ANCHORS_IN_FLIGHT: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+    // SPV proofs for batches whose anchor reached final depth, keyed by batch sequence number
+    static ANCHOR_PROOFS: RefCell<StableBTreeMap<u64, AnchorProof, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ANCHOR_PROOFS_MEMORY)));
+    // Batches whose anchor transaction is still being polled towards final depth
+    static TRACKED_ANCHORS: RefCell<StableBTreeMap<u64, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::TRACKED_ANCHORS_MEMORY)));
+    // Batches still waiting for their threshold signature
+    static SIGNING_QUEUE: RefCell<StableBTreeMap<u64, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::SIGNING_QUEUE_MEMORY)));
+    static SIGNING_ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+#[init]
+fn init(args: Option<InitArgs>) {
+    storage::init_schema();
+    config::apply_init_args(args.unwrap_or_default()).unwrap_or_else(|e| ic_cdk::trap(&e));
+    certification::commit();
+    scheduler::start();
+}
+
+#[post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    storage::migrate();
+    if let Some(args) = args {
+        config::apply_upgrade_args(args).unwrap_or_else(|e| ic_cdk::trap(&e));
+    }
+    certification::start_rebuild();
+    scheduler::start();
+}
+
+// Host-friendly time helper: uses ic_cdk::api::time in WASM, std time in host tests
+fn now() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::time()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+    }
+}
+
+/// `receipt_<sequence>_<hash>` where hash is the first 16 bytes of
+/// sha256(issuer || data_hash || sequence), so anyone holding the receipt can
+/// recompute its ID.
+pub fn derive_receipt_id(issuer: &Principal, data_hash: &str, sequence: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(issuer.as_slice());
+    hasher.update(data_hash.as_bytes());
+    hasher.update(sequence.to_be_bytes());
+    let digest = hasher.finalize();
+    format!("receipt_{}_{}", sequence, hex::encode(&digest[..16]))
+}
+
+fn next_receipt_sequence() -> u64 {
+    RECEIPT_COUNTER.with(|c| {
+        let mut counter = c.borrow_mut();
+        let sequence = *counter.get();
+        counter.set(sequence + 1).expect("failed to advance receipt counter");
+        sequence
+    })
+}
+
+// Oldest receipt or receipt event waiting for a batch
+fn oldest_pending_timestamp() -> Option<u64> {
+    let id = PENDING_RECEIPTS.with(|p| p.borrow().first_key_value().map(|(_, id)| id));
+    let receipt_at = id.and_then(|id| RECEIPTS.with(|r| r.borrow().get(&id).map(|receipt| receipt.timestamp)));
+    receipt_at.into_iter().chain(events::oldest_pending_at()).min()
+}
+
+// Leaves the next batch will commit
+fn pending_commitments() -> u64 {
+    PENDING_RECEIPTS.with(|p| p.borrow().len()) + events::pending_count()
+}
+
+// Every batch ever cut is either still local or archived, so the next seq is
+// one past the highest of either; counting local batches would reuse archived seqs
+fn next_batch_seq() -> u64 {
+    let local = BATCHES.with(|b| b.borrow().last_key_value().map(|(seq, _)| seq));
+    local.max(archive::last_archived_seq()).map(|seq| seq + 1).unwrap_or(0)
+}
+
+fn has_due_anchors(now: u64) -> bool {
+    ANCHOR_QUEUE.with(|q| q.borrow().iter().any(|(_, retry)| retry.is_due(now)))
 }
 
 #[update]
-pub fn issue_receipt(data_hash: String) -> String {
-    let receipt_id = format!("receipt_{}", ic_cdk::api::time());
+pub fn issue_receipt(data_hash: DataHash, metadata: Option<ReceiptMetadata>) -> Result<String, IssueError> {
+    let issuer = access::require(Role::Issuer).map_err(IssueError::Unauthorized)?;
+    issue(issuer, data_hash, metadata)
+}
+
+// Issues on behalf of `issuer`; callers are responsible for authorizing it
+fn issue(issuer: Principal, data_hash: DataHash, metadata: Option<ReceiptMetadata>) -> Result<String, IssueError> {
+    if let Some(metadata) = &metadata {
+        metadata.validate()?;
+    }
+    let algorithm = data_hash.algorithm;
+    let data_hash = data_hash.normalize()?;
+    let dedupe_key = format!("{}:{}", issuer, data_hash);
+    if config::get().dedupe_receipts {
+        if let Some(receipt_id) = ISSUER_HASHES.with(|h| h.borrow().get(&dedupe_key)) {
+            return Err(IssueError::Duplicate { receipt_id });
+        }
+    }
+
+    let sequence = next_receipt_sequence();
+    let receipt_id = derive_receipt_id(&issuer, &data_hash, sequence);
     let receipt = Receipt {
         id: receipt_id.clone(),
         data_hash,
-        timestamp: ic_cdk::api::time(),
+        hash_algorithm: algorithm,
+        timestamp: now(),
         merkle_proof: vec![],
+        issuer,
+        sequence,
+        metadata,
+        leaf_version: metadata::LEAF_VERSION,
     };
     
-    RECEIPTS.with(|r| r.borrow_mut().insert(receipt_id.clone(), receipt.clone()));
-    PENDING_RECEIPTS.with(|p| p.borrow_mut().push(receipt));
+    index::index_receipt(&receipt);
+    let leaf = receipt_leaf(&receipt);
+    certification::certify_receipt(&receipt_id, leaf);
+    icrc3::receipt_issued(&receipt, &leaf);
+    RECEIPTS.with(|r| r.borrow_mut().insert(receipt_id.clone(), receipt));
+    ISSUER_HASHES.with(|h| h.borrow_mut().insert(dedupe_key, receipt_id.clone()));
+    PENDING_RECEIPTS.with(|p| {
+        let mut pending = p.borrow_mut();
+        let seq = pending.last_key_value().map(|(k, _)| k + 1).unwrap_or(0);
+        pending.insert(seq, receipt_id.clone());
+    });
     
-    receipt_id
+    Ok(receipt_id)
 }
 
 #[update]
-pub fn batch() -> String {
-    let pending = PENDING_RECEIPTS.with(|p| {
+pub fn batch() -> Result<String, String> {
+    access::require(Role::Admin)?;
+    Ok(batch_pending())
+}
+
+fn batch_pending() -> String {
+    let pending_ids: Vec<String> = PENDING_RECEIPTS.with(|p| {
         let mut pending = p.borrow_mut();
-        let batch = pending.clone();
-        pending.clear();
-        batch
+        std::iter::from_fn(|| pending.pop_first().map(|(_, id)| id)).collect()
     });
+    let mut pending: Vec<Receipt> = RECEIPTS.with(|r| {
+        let receipts = r.borrow();
+        pending_ids.iter().filter_map(|id| receipts.get(id)).collect()
+    });
+    let pending_events = events::take_pending();
     
-    if pending.is_empty() {
+    if pending.is_empty() && pending_events.is_empty() {
         return "No pending receipts".to_string();
     }
     
-    // Build Merkle tree (simplified - just hash all receipt IDs)
-    let mut hasher = Sha256::new();
-    for receipt in &pending {
-        hasher.update(receipt.id.as_bytes());
+    // Build the Merkle tree over receipt leaves, then event leaves, and hand
+    // every receipt and event its path
+    let leaves = pending.iter().map(receipt_leaf).chain(pending_events.iter().map(events::leaf)).collect();
+    let tree = MerkleTree::build(leaves);
+    let root_hash = tree.root().expect("non-empty batch has a root");
+    let root = hex::encode(root_hash);
+    
+    RECEIPTS.with(|r| {
+        let mut receipts = r.borrow_mut();
+        for (index, receipt) in pending.iter_mut().enumerate() {
+            receipt.merkle_proof = tree.proof(index).iter().map(|s| s.encode()).collect();
+            receipts.insert(receipt.id.clone(), receipt.clone());
+        }
+    });
+    for (index, event) in pending_events.iter().enumerate() {
+        let proof = tree.proof(pending.len() + index).iter().map(|s| s.encode()).collect();
+        events::record_commitment(event.seq, &root, proof);
     }
-    let root = format!("{:x}", hasher.finalize());
     
     let batch = MerkleBatch {
         root: root.clone(),
         receipts: pending,
-        created_at: ic_cdk::api::time(),
-        btc_anchor_txid: None,
-        btc_block_height: None,
+        created_at: now(),
+        anchor_status: AnchorStatus::Unanchored,
+        anchor_mode: None,
+        mmr_size: mmr::append(root_hash),
+        signature: None,
+        chain_anchors: Vec::new(),
+        events: pending_events.iter().map(|event| event.seq).collect(),
     };
     
-    BATCHES.with(|b| b.borrow_mut().push(batch));
+    let created_at = batch.created_at;
+    let seq = next_batch_seq();
+    BATCHES.with(|b| {
+        let mut batches = b.borrow_mut();
+        let chain_anchors = chains::start(seq, created_at);
+        batches.insert(seq, MerkleBatch { chain_anchors, ..batch.clone() });
+    });
+    BATCH_ROOTS.with(|r| r.borrow_mut().insert(root.clone(), seq));
+    RECEIPT_BATCH.with(|r| {
+        let mut receipt_batch = r.borrow_mut();
+        for receipt_id in &pending_ids {
+            receipt_batch.insert(receipt_id.clone(), seq);
+        }
+    });
+    ANCHOR_QUEUE.with(|q| q.borrow_mut().insert(seq, AnchorRetry::new(created_at)));
+    SIGNING_QUEUE.with(|q| q.borrow_mut().insert(seq, ()));
+    certification::certify_batch(&root, root_hash);
+    icrc3::batch_cut(&batch, seq);
+    notifications::publish(
+        Notification::BatchCreated { seq, root: root.clone(), receipt_count: pending_ids.len() as u64 },
+        created_at,
+    );
+    #[cfg(target_arch = "wasm32")]
+    ic_cdk::spawn(sign_queued());
     
     root
 }
 
-#[update]
-pub async fn anchor() -> String {
-    match BATCHES.with(|b| b.borrow().last().cloned()) {
-        Some(mut batch) => {
-            // Call BTC signer canister to create anchor transaction
-            // Use a fallback approach - try real BTC integration first, then mock
-            let btc_result = match ic_cdk::api::call::call_raw(
-                Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap(),
-                "create_and_broadcast_anchor",
-                candid::encode_args((batch.root.clone(), 1000u64)).unwrap().as_slice(),
-                25_000_000_000
-            ).await {
-                Ok(response) => {
-                    match candid::decode_one::<Result<String, String>>(&response) {
-                        Ok(Ok(txid)) => Ok(txid),
-                        Ok(Err(e)) => Err(e),
-                        Err(_) => Err("Failed to decode response".to_string()),
+fn has_unsigned_batches() -> bool {
+    SIGNING_QUEUE.with(|q| !q.borrow().is_empty())
+}
+
+// Held for a signing run; dropping it, even when the run traps mid-way,
+// lets the next scheduler tick start another
+struct SigningGuard;
+
+impl SigningGuard {
+    fn acquire() -> Option<Self> {
+        (!SIGNING_ACTIVE.with(|a| a.replace(true))).then_some(SigningGuard)
+    }
+}
+
+impl Drop for SigningGuard {
+    fn drop(&mut self) {
+        SIGNING_ACTIVE.with(|a| a.set(false));
+    }
+}
+
+// Signs queued batches oldest first. Only one run at a time; a failure leaves
+// the rest queued for the next scheduler tick.
+async fn sign_queued() {
+    let Some(_guard) = SigningGuard::acquire() else {
+        return;
+    };
+    let key_name = signing::key_name(config::get().network);
+    while let Some((seq, ())) = SIGNING_QUEUE.with(|q| q.borrow().first_key_value()) {
+        let Some(batch) = BATCHES.with(|b| b.borrow().get(&seq)) else {
+            SIGNING_QUEUE.with(|q| q.borrow_mut().remove(&seq));
+            continue;
+        };
+        let root = match merkle::decode_hash(&batch.root) {
+            Ok(root) => root,
+            Err(e) => {
+                // Cannot be signed on a later tick either
+                ic_cdk::println!("Dropping batch {} from the signing queue: {}", seq, e);
+                SIGNING_QUEUE.with(|q| q.borrow_mut().remove(&seq));
+                continue;
+            }
+        };
+        match signing::sign(key_name, signing::message(&root, batch.created_at)).await {
+            Ok(signature) => {
+                // Re-read: the batch may have been anchored while we were waiting
+                BATCHES.with(|b| {
+                    let mut batches = b.borrow_mut();
+                    if let Some(mut batch) = batches.get(&seq) {
+                        batch.signature = Some(signature);
+                        batches.insert(seq, batch);
                     }
+                });
+                SIGNING_QUEUE.with(|q| q.borrow_mut().remove(&seq));
+            }
+            Err(e) => {
+                ic_cdk::println!("Signing batch {} failed: {}", batch.root, e);
+                break;
+            }
+        }
+    }
+}
+
+async fn broadcast_anchor(root: &str) -> Result<String, String> {
+    let config = config::get();
+    let response = ic_cdk::api::call::call_raw(
+        config.btc_signer,
+        "create_and_broadcast_anchor",
+        candid::encode_args((root.to_string(), config.fee_rate)).unwrap().as_slice(),
+        config.anchor_cycles
+    )
+    .await
+    .map_err(|(code, msg)| format!("BTC signer call failed: {:?} - {}", code, msg))?;
+    
+    candid::decode_one::<Result<String, String>>(&response)
+        .map_err(|e| format!("Failed to decode response: {}", e))?
+}
+
+// Held while a batch's anchor call is outstanding so concurrent callers cannot double-broadcast it
+struct AnchorGuard(u64);
+
+impl AnchorGuard {
+    fn acquire(seq: u64) -> Result<Self, String> {
+        if ANCHORS_IN_FLIGHT.with(|f| f.borrow_mut().insert(seq)) {
+            Ok(AnchorGuard(seq))
+        } else {
+            Err("Anchor already in progress for this batch".to_string())
+        }
+    }
+}
+
+impl Drop for AnchorGuard {
+    fn drop(&mut self) {
+        ANCHORS_IN_FLIGHT.with(|f| f.borrow_mut().remove(&self.0));
+    }
+}
+
+async fn anchor_seq(seq: u64) -> Result<String, String> {
+    let _guard = AnchorGuard::acquire(seq)?;
+    let batch = BATCHES
+        .with(|b| b.borrow().get(&seq))
+        .ok_or_else(|| format!("Batch {} not found", seq))?;
+    // A second broadcast would race the transaction already in flight
+    if let Some(txid) = batch.anchor_status.txid() {
+        ANCHOR_QUEUE.with(|q| q.borrow_mut().remove(&seq));
+        return Err(format!("Batch {} is already anchored with txid {}", batch.root, txid));
+    }
+    
+    let commitment = batch.anchor_commitment()?;
+    let mode = config::mode();
+    let btc_result = match mode {
+        AnchorMode::Live => broadcast_anchor(&commitment).await,
+        AnchorMode::Mock => Ok(format!("mock_btc_txid_{}", &commitment[..8])),
+    };
+    
+    // Re-read: the batch may have been signed while the BTC call was outstanding
+    let mut batch = BATCHES.with(|b| b.borrow().get(&seq)).unwrap_or(batch);
+    match btc_result {
+        Ok(txid) => {
+            batch.anchor_status = AnchorStatus::Broadcast { txid: txid.clone(), broadcast_at: now() };
+            batch.anchor_mode = Some(mode);
+            BATCHES.with(|b| b.borrow_mut().insert(seq, batch.clone()));
+            ANCHOR_QUEUE.with(|q| q.borrow_mut().remove(&seq));
+            // Mock txids never reach a chain, so there is nothing to poll
+            if mode == AnchorMode::Live {
+                TRACKED_ANCHORS.with(|t| t.borrow_mut().insert(seq, ()));
+            }
+            scheduler::record_anchor(now());
+            icrc3::anchor_broadcast(seq, &batch.root, None, &txid, now());
+            notifications::publish(
+                Notification::AnchorBroadcast { seq, root: batch.root.clone(), chain: None, txid: txid.clone() },
+                now(),
+            );
+            
+            Ok(format!("Anchored batch {} to BTC with txid: {}", batch.root, txid))
+        }
+        // Never overwrite a txid that landed meanwhile; it stays tracked
+        Err(e) if batch.anchor_status.txid().is_some() => Err(format!("Failed to anchor batch {}: {}", batch.root, e)),
+        Err(e) => {
+            batch.anchor_status = AnchorStatus::Failed { reason: e.clone(), failed_at: now() };
+            BATCHES.with(|b| b.borrow_mut().insert(seq, batch.clone()));
+            ANCHOR_QUEUE.with(|q| {
+                let mut queue = q.borrow_mut();
+                let retry = queue.get(&seq).unwrap_or_else(|| AnchorRetry::new(now()));
+                queue.insert(seq, retry.failed(e.clone(), now()));
+            });
+            Err(format!("Failed to anchor batch {}: {}", batch.root, e))
+        }
+    }
+}
+
+// Anchors queued batches one at a time, oldest first; `due_only` respects retry backoff
+async fn anchor_queued(due_only: bool) -> Vec<Result<String, String>> {
+    let now = now();
+    let queued: Vec<u64> = ANCHOR_QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .filter(|(_, retry)| !due_only || retry.is_due(now))
+            .map(|(seq, _)| seq)
+            .collect()
+    });
+    
+    let mut results = Vec::with_capacity(queued.len());
+    for seq in queued {
+        results.push(anchor_seq(seq).await);
+    }
+    results
+}
+
+#[update]
+pub async fn anchor() -> Result<String, String> {
+    access::require(Role::Admin)?;
+    let seq = BATCHES
+        .with(|b| b.borrow().last_key_value().map(|(seq, _)| seq))
+        .ok_or_else(|| "No batches to anchor".to_string())?;
+    anchor_seq(seq).await
+}
+
+#[update]
+pub async fn anchor_batch(root: String) -> Result<String, String> {
+    access::require(Role::Admin)?;
+    let seq = BATCH_ROOTS
+        .with(|r| r.borrow().get(&root))
+        .ok_or_else(|| format!("Batch {} not found", root))?;
+    anchor_seq(seq).await
+}
+
+#[update]
+pub async fn anchor_all() -> Result<Vec<Result<String, String>>, String> {
+    access::require(Role::Admin)?;
+    Ok(anchor_queued(false).await)
+}
+
+// Broadcasts every queued anchor on the enabled non-BTC targets, ignoring backoff
+#[update]
+pub fn anchor_chains() -> Result<Vec<Result<String, String>>, String> {
+    access::require(Role::Admin)?;
+    Ok(chains::anchor_queued(false))
+}
+
+#[update]
+pub fn set_anchor_target(target: AnchorTarget) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    chains::set_target(target)
+}
+
+#[update]
+pub fn remove_anchor_target(chain: Chain) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    chains::remove_target(&chain)
+}
+
+#[query]
+pub fn get_anchor_targets() -> Vec<AnchorTarget> {
+    chains::targets()
+}
+
+// The proof_of_state_archive module installed into newly spawned archives
+#[update]
+pub fn set_archive_wasm(wasm: Vec<u8>) -> Result<(), String> {
+    access::require_controller()?;
+    archive::set_wasm(wasm)
+}
+
+// Moves every settled batch past the archive threshold now; returns how many moved
+#[update]
+pub async fn archive_batches() -> Result<u64, String> {
+    access::require(Role::Admin)?;
+    archive::archive_due().await
+}
+
+#[query]
+pub fn get_archives() -> Vec<ArchiveInfo> {
+    archive::archives()
+}
+
+#[query]
+pub fn locate_batch(root: String) -> Option<BatchLocation> {
+    archive::locate(&root)
+}
+
+// Subscribes the calling canister, which must hold the Subscriber role;
+// `method` receives a one-way NotificationEnvelope for each selected kind, or
+// every kind when None
+#[update]
+pub fn subscribe(method: String, kinds: Option<Vec<NotificationKind>>) -> Result<Subscription, String> {
+    let caller = access::require(Role::Subscriber)?;
+    notifications::subscribe(caller, method, kinds, now())
+}
+
+#[update]
+pub fn unsubscribe() -> Result<(), String> {
+    notifications::unsubscribe(&access::caller())
+}
+
+#[update]
+pub fn remove_subscriber(subscriber: Principal) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    notifications::unsubscribe(&subscriber)
+}
+
+#[query]
+pub fn get_subscriptions() -> Vec<Subscription> {
+    notifications::subscriptions()
+}
+
+// Notifications that could not be enqueued and are waiting for a retry
+#[query]
+pub fn get_notification_queue() -> Vec<QueuedDelivery> {
+    notifications::queued()
+}
+
+#[query]
+pub fn get_anchor_queue() -> Vec<AnchorQueueEntry> {
+    ANCHOR_QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .filter_map(|(seq, retry)| {
+                let batch = BATCHES.with(|b| b.borrow().get(&seq))?;
+                Some(AnchorQueueEntry {
+                    batch_root: batch.root,
+                    attempts: retry.attempts,
+                    next_attempt_at: retry.next_attempt_at,
+                    last_error: retry.last_error,
+                })
+            })
+            .collect()
+    })
+}
+
+async fn fetch_transaction_status(txid: String) -> Result<TransactionStatus, String> {
+    let (result,): (Result<TransactionStatus, String>,) = ic_cdk::call(
+        config::get().btc_signer,
+        "get_transaction_status",
+        (txid,),
+    )
+    .await
+    .map_err(|(code, msg)| format!("BTC signer call failed: {:?} - {}", code, msg))?;
+    result
+}
+
+async fn fetch_anchor_proof(txid: String) -> Result<AnchorProof, String> {
+    let (result,): (Result<AnchorProof, String>,) = ic_cdk::call(
+        config::get().btc_signer,
+        "get_anchor_proof",
+        (txid,),
+    )
+    .await
+    .map_err(|(code, msg)| format!("BTC signer call failed: {:?} - {}", code, msg))?;
+    result
+}
+
+// Polls every tracked anchor once and advances its status. Final anchors stop
+// being tracked once their SPV proof is stored for bundle export.
+async fn poll_anchor_confirmations() {
+    let required = scheduler::state().policy.required_confirmations;
+    let tracked: Vec<u64> = TRACKED_ANCHORS.with(|t| t.borrow().iter().map(|(seq, _)| seq).collect());
+    
+    for seq in tracked {
+        let Some(txid) = BATCHES.with(|b| b.borrow().get(&seq))
+            .and_then(|batch| batch.anchor_status.txid().map(str::to_string))
+        else {
+            TRACKED_ANCHORS.with(|t| t.borrow_mut().remove(&seq));
+            continue;
+        };
+        
+        match fetch_transaction_status(txid.clone()).await {
+            Ok(status) => {
+                // Re-read after the await: the batch may have been re-anchored meanwhile
+                let Some(mut batch) = BATCHES.with(|b| b.borrow().get(&seq)) else { continue };
+                if batch.anchor_status.txid() != Some(txid.as_str()) {
+                    continue;
                 }
-                Err(_) => {
-                    // Fallback to mock for testing
-                    Ok(format!("mock_btc_txid_{}", &batch.root[..8]))
+                let was_final = batch.anchor_status.is_final(required);
+                batch.anchor_status = batch.anchor_status.observe(&status, now());
+                let is_final = batch.anchor_status.is_final(required);
+                let root = batch.root.clone();
+                BATCHES.with(|b| b.borrow_mut().insert(seq, batch));
+                if is_final && !was_final {
+                    icrc3::anchor_confirmed(seq, &root, None, &txid, now());
+                    let txid = txid.clone();
+                    notifications::publish(Notification::AnchorConfirmed { seq, root, chain: None, txid }, now());
                 }
-            };
-            
-            match btc_result {
-                Ok(txid) => {
-                    batch.btc_anchor_txid = Some(txid.clone());
-                    batch.btc_block_height = Some(800000); // Will be updated when confirmed
-                    
-                    BATCHES.with(|b| {
-                        let mut batches = b.borrow_mut();
-                        if let Some(last) = batches.last_mut() {
-                            last.btc_anchor_txid = batch.btc_anchor_txid.clone();
-                            last.btc_block_height = batch.btc_block_height;
+                if is_final {
+                    match fetch_anchor_proof(txid.clone()).await {
+                        Ok(proof) => {
+                            ANCHOR_PROOFS.with(|p| p.borrow_mut().insert(seq, proof));
+                            TRACKED_ANCHORS.with(|t| t.borrow_mut().remove(&seq));
                         }
-                    });
-                    
-                    format!("Anchored batch {} to BTC with txid: {}", batch.root, txid)
+                        Err(e) => ic_cdk::println!("Fetching SPV proof for {} failed: {}", txid, e),
+                    }
                 }
-                Err(e) => format!("Failed to anchor batch: {}", e),
             }
+            Err(e) => ic_cdk::println!("Confirmation check for {} failed: {}", txid, e),
         }
-        None => "No batches to anchor".to_string(),
     }
 }
 
-#[derive(CandidType, Deserialize, Clone)]
+#[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct BurnState {
     pub receipt_id: String,
     pub message_id: String,
     pub burned: bool,
     pub timestamp: u64,
+    pub updated_by: Principal,
+    // The audit event recording the burn
+    pub event_seq: u64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    // Stands in for cross_chain_service's answer in host tests
+    static HOST_QUORUM_REACHED: std::cell::Cell<bool> = const { std::cell::Cell::new(true) };
+}
+
+// Asks cross_chain_service whether the message exists and was attested by
+// enough of its registered validators. Host tests have no cross_chain_service
+// and get `HOST_QUORUM_REACHED` instead.
+async fn message_quorum_reached(message_id: &str) -> Result<bool, String> {
+    let service = config::get()
+        .cross_chain_service
+        .ok_or_else(|| "cross_chain_service is not configured".to_string())?;
+    #[cfg(target_arch = "wasm32")]
+    {
+        let (result,): (Result<bool, String>,) = ic_cdk::call(service, "is_quorum_reached", (message_id.to_string(),))
+            .await
+            .map_err(|(code, msg)| format!("cross_chain_service call failed: {:?} - {}", code, msg))?;
+        result
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (service, message_id);
+        Ok(HOST_QUORUM_REACHED.with(|q| q.get()))
+    }
+}
+
+// A receipt is live until it is burned, revoked or superseded
+fn check_live(receipt_id: &str) -> Result<(), String> {
+    // Archived receipts keep their batch membership
+    let known = RECEIPTS.with(|r| r.borrow().contains_key(&receipt_id.to_string()))
+        || RECEIPT_BATCH.with(|r| r.borrow().contains_key(&receipt_id.to_string()));
+    if !known {
+        return Err(format!("Receipt {} not found", receipt_id));
+    }
+    if let Some(event) = events::final_event(receipt_id) {
+        return Err(format!("Receipt {} is already {}", receipt_id, event.kind.describe()));
+    }
+    Ok(())
+}
+
+async fn live_receipt(receipt_id: &str) -> Result<Receipt, String> {
+    check_live(receipt_id)?;
+    let receipt = find_receipt(receipt_id).await?;
+    // Re-check: the receipt may have been retired while its archive answered
+    check_live(receipt_id)?;
+    Ok(receipt)
+}
+
+fn check_burnable(receipt_id: &str, message_id: &str) -> Result<(), String> {
+    check_live(receipt_id)?;
+    if let Some(burned) = BURN_MESSAGES.with(|m| m.borrow().get(&message_id.to_string())) {
+        return Err(format!("Message {} already burned receipt {}", message_id, burned));
+    }
+    Ok(())
+}
+
+/// Burns a receipt on the strength of a quorum-attested cross-chain message.
+/// Burns are final; the event is logged and committed in the next batch.
+#[update]
+pub async fn burn_receipt(receipt_id: String, message_id: String) -> Result<BurnState, String> {
+    let caller = access::require(Role::Burner)?;
+    if message_id.is_empty() {
+        return Err("message_id must not be empty".to_string());
+    }
+    check_burnable(&receipt_id, &message_id)?;
+    if !message_quorum_reached(&message_id).await? {
+        return Err(format!("Message {} has not reached DVN quorum", message_id));
+    }
+    // Re-check: another burn may have landed while cross_chain_service answered
+    check_burnable(&receipt_id, &message_id)?;
+
+    let event = events::append(
+        receipt_id.clone(),
+        ReceiptEventKind::Burned { message_id: message_id.clone() },
+        caller,
+        now(),
+    );
+    let state = BurnState {
+        receipt_id: receipt_id.clone(),
+        message_id: message_id.clone(),
+        burned: true,
+        timestamp: event.timestamp,
+        updated_by: caller,
+        event_seq: event.seq,
+    };
+    BURN_STATES.with(|b| b.borrow_mut().insert(receipt_id.clone(), state.clone()));
+    BURN_MESSAGES.with(|m| m.borrow_mut().insert(message_id.clone(), receipt_id.clone()));
+    notifications::publish(
+        Notification::BurnStateChanged { receipt_id, message_id, burned: true, event_seq: state.event_seq },
+        state.timestamp,
+    );
+    Ok(state)
 }
 
+// Kept for existing callers; prefer burn_receipt. Only `burned = true` is accepted.
 #[update]
-pub fn set_burn_state(receipt_id: String, message_id: String, burned: bool) -> String {
-    let state = BurnState { receipt_id: receipt_id.clone(), message_id, burned, timestamp: ic_cdk::api::time() };
-    BURN_STATES.with(|b| { b.borrow_mut().insert(receipt_id.clone(), state); });
-    "ok".to_string()
+pub async fn set_burn_state(receipt_id: String, message_id: String, burned: bool) -> Result<(), String> {
+    if !burned {
+        access::require(Role::Burner)?;
+        return Err("Burns are final and cannot be reverted".to_string());
+    }
+    burn_receipt(receipt_id, message_id).await.map(|_| ())
+}
+
+/// Revokes a receipt. The revocation is final and, like a burn, is logged and
+/// committed in the next batch so holders of older proofs can discover it.
+#[update]
+pub async fn revoke_receipt(receipt_id: String, reason: String) -> Result<ReceiptEvent, String> {
+    access::require_role_or_admin(Role::Issuer)?;
+    if reason.is_empty() || reason.len() > events::MAX_REASON_LEN {
+        return Err(format!("reason must be 1-{} bytes", events::MAX_REASON_LEN));
+    }
+    let receipt = live_receipt(&receipt_id).await?;
+    let caller = access::require_owner(Role::Issuer, receipt.issuer)?;
+    Ok(events::append(receipt_id, ReceiptEventKind::Revoked { reason }, caller, now()))
+}
+
+/// Replaces a receipt with a new one for `new_data_hash` carrying the same
+/// issuer and metadata, and records the supersession on the old receipt.
+/// Either the issuer or an admin may supersede. Returns the new receipt's ID.
+#[update]
+pub async fn supersede_receipt(old_receipt_id: String, new_data_hash: DataHash) -> Result<String, String> {
+    access::require_role_or_admin(Role::Issuer)?;
+    let new_hash = new_data_hash.normalize().map_err(|e| e.to_string())?;
+    let old = live_receipt(&old_receipt_id).await?;
+    let caller = access::require_owner(Role::Issuer, old.issuer)?;
+    if new_hash == old.data_hash {
+        return Err("The new data hash must differ from the superseded one".to_string());
+    }
+    let new_receipt_id = issue(old.issuer, new_data_hash, old.metadata).map_err(|e| e.to_string())?;
+    events::append(old_receipt_id, ReceiptEventKind::Superseded { new_receipt_id: new_receipt_id.clone() }, caller, now());
+    Ok(new_receipt_id)
+}
+
+// The receipt's audit trail, oldest first, with each event's batch commitment
+#[query]
+pub fn get_receipt_events(receipt_id: String) -> Vec<ReceiptEvent> {
+    events::history(&receipt_id)
+}
+
+// Checks that a committed event's Merkle path leads to a known batch root
+#[query]
+pub fn verify_receipt_event(event_seq: u64) -> Result<bool, String> {
+    let event = events::get(event_seq).ok_or_else(|| format!("Event {} not found", event_seq))?;
+    let Some(batch_root) = &event.batch_root else {
+        return Ok(false);
+    };
+    let root = merkle::compute_root_encoded(&hex::encode(events::leaf(&event)), &event.merkle_proof)?;
+    Ok(hex::encode(root) == *batch_root && BATCH_ROOTS.with(|r| r.borrow().contains_key(batch_root)))
 }
 
 #[query]
 pub fn get_burn_state(receipt_id: String) -> Option<BurnState> {
-    BURN_STATES.with(|b| b.borrow().get(&receipt_id).cloned())
+    BURN_STATES.with(|b| b.borrow().get(&receipt_id))
+}
+
+// Receipts whose batch was archived are only served by get_receipt and verify_receipt
+fn local_receipt(receipt_id: &str) -> Result<Receipt, String> {
+    RECEIPTS.with(|r| r.borrow().get(&receipt_id.to_string())).ok_or_else(|| {
+        match RECEIPT_BATCH.with(|r| r.borrow().get(&receipt_id.to_string())).and_then(archive::archive_of) {
+            Some(archive) => format!("Receipt {} is archived in {}", receipt_id, archive),
+            None => format!("Receipt {} not found", receipt_id),
+        }
+    })
+}
+
+// Looks the receipt up locally, then in the archive holding its batch
+async fn find_receipt(receipt_id: &str) -> Result<Receipt, String> {
+    if let Some(receipt) = RECEIPTS.with(|r| r.borrow().get(&receipt_id.to_string())) {
+        return Ok(receipt);
+    }
+    archive::fetch_receipt(receipt_id).await?.ok_or_else(|| format!("Receipt {} not found", receipt_id))
+}
+
+#[query(composite = true)]
+pub async fn get_receipt(receipt_id: String) -> Option<Receipt> {
+    find_receipt(&receipt_id).await.ok()
+}
+
+fn receipts_by_id(ids: Vec<String>) -> Vec<Receipt> {
+    RECEIPTS.with(|r| {
+        let receipts = r.borrow();
+        ids.iter().filter_map(|id| receipts.get(id)).collect()
+    })
+}
+
+// Local receipts in full, archived ones as a pointer to the archive holding them
+fn receipt_page(ids: Vec<String>) -> ReceiptPage {
+    let mut page = ReceiptPage { receipts: Vec::new(), archived: Vec::new() };
+    for receipt_id in ids {
+        if let Some(receipt) = RECEIPTS.with(|r| r.borrow().get(&receipt_id)) {
+            page.receipts.push(receipt);
+        } else if let Some(archive) = RECEIPT_BATCH.with(|r| r.borrow().get(&receipt_id)).and_then(archive::archive_of) {
+            page.archived.push(ArchivedReceipt { receipt_id, archive });
+        }
+    }
+    page
+}
+
+#[query]
+pub fn get_receipts_by_iqube(iqube_id: String, offset: u64, limit: u64) -> ReceiptPage {
+    receipt_page(index::by_iqube(&iqube_id, offset, limit))
+}
+
+#[query]
+pub fn get_receipts_by_issuer(issuer: Principal, offset: u64, limit: u64) -> ReceiptPage {
+    receipt_page(index::by_issuer(&issuer, offset, limit))
+}
+
+#[query]
+pub fn get_certified_receipt(receipt_id: String) -> Result<CertifiedReceipt, String> {
+    let receipt = local_receipt(&receipt_id)?;
+    Ok(CertifiedReceipt {
+        receipt,
+        certificate: certification::certificate()?,
+        witness: certification::receipt_witness(&receipt_id),
+    })
+}
+
+#[query]
+pub fn get_certified_batch(root: String) -> Result<CertifiedBatch, String> {
+    let seq = BATCH_ROOTS
+        .with(|r| r.borrow().get(&root))
+        .ok_or_else(|| format!("Batch {} not found", root))?;
+    let batch = BATCHES.with(|b| b.borrow().get(&seq)).ok_or_else(|| format!("Batch {} not found", root))?;
+    Ok(CertifiedBatch {
+        batch: batch.summary(seq),
+        certificate: certification::certificate()?,
+        witness: certification::batch_witness(&root),
+    })
+}
+
+/// Blocks of the ICRC-3 log; see `icrc3` for the block layout.
+#[query]
+pub fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    icrc3::get_blocks(args)
+}
+
+#[query]
+pub fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    let certificate = certification::certificate().ok()?;
+    let hash_tree = certification::tip_witness()?;
+    Some(DataCertificate { certificate, hash_tree })
+}
+
+// The block log is never archived
+#[query]
+pub fn icrc3_get_archives(_args: GetArchivesArgs) -> Vec<ArchiveRange> {
+    vec![]
+}
+
+#[query]
+pub fn export_proof_bundle(receipt_id: String) -> Result<ExportedBundle, String> {
+    let receipt = local_receipt(&receipt_id)?;
+    let seq = RECEIPT_BATCH
+        .with(|r| r.borrow().get(&receipt_id))
+        .ok_or_else(|| format!("Receipt {} is not batched yet", receipt_id))?;
+    let batch = BATCHES.with(|b| b.borrow().get(&seq)).ok_or_else(|| format!("Batch {} not found", seq))?;
+    let mmr_proof = batch.mmr_proof(seq)?;
+
+    let bundle = ProofBundle {
+        version: bundle::BUNDLE_VERSION,
+        leaf_hash: hex::encode(receipt_leaf(&receipt)),
+        receipt,
+        batch_root: batch.root,
+        anchor_txid: batch.anchor_status.txid().map(str::to_string),
+        anchor_mock: batch.anchor_mode == Some(AnchorMode::Mock),
+        anchor_proof: ANCHOR_PROOFS
+            .with(|p| p.borrow().get(&seq))
+            .filter(|proof| batch.anchor_status.txid() == Some(proof.txid.as_str())),
+        mmr_proof,
+    };
+    bundle.export()
+}
+
+// The receipt, its batch's MMR path and the SPV proof of the batch's final anchor
+fn receipt_anchor_proof(receipt_id: &str) -> Result<(Receipt, MmrInclusionProof, AnchorProof), String> {
+    let receipt = local_receipt(receipt_id)?;
+    let seq = RECEIPT_BATCH
+        .with(|r| r.borrow().get(&receipt.id))
+        .ok_or_else(|| format!("Receipt {} is not batched yet", receipt_id))?;
+    let batch = BATCHES.with(|b| b.borrow().get(&seq)).ok_or_else(|| format!("Batch {} not found", seq))?;
+    let proof = ANCHOR_PROOFS
+        .with(|p| p.borrow().get(&seq))
+        .filter(|proof| batch.anchor_status.txid() == Some(proof.txid.as_str()))
+        .ok_or_else(|| format!("Batch {} has no final BTC anchor yet", batch.root))?;
+    Ok((receipt, batch.mmr_proof(seq)?, proof))
+}
+
+#[query]
+pub fn export_ots(receipt_id: String) -> Result<Vec<u8>, String> {
+    let (receipt, mmr_proof, proof) = receipt_anchor_proof(&receipt_id)?;
+    Ok(ots::receipt_timestamp(&receipt, &mmr_proof, &proof)?.serialize())
+}
+
+// Returns the block heights at which the submitted .ots proof is confirmed
+#[query]
+pub fn verify_ots(receipt_id: String, proof_file: Vec<u8>) -> Result<Vec<u64>, String> {
+    let (receipt, _, proof) = receipt_anchor_proof(&receipt_id)?;
+    let timestamp = ots::DetachedTimestamp::parse(&proof_file)?;
+    ots::verify_receipt_timestamp(&receipt, &timestamp, &proof)
+}
+
+// Looks the batch up locally, then in the archive holding it
+async fn batch_at(seq: u64) -> Result<Option<MerkleBatch>, String> {
+    match BATCHES.with(|b| b.borrow().get(&seq)) {
+        Some(batch) => Ok(Some(batch)),
+        None => archive::fetch_batch(seq).await,
+    }
+}
+
+#[query(composite = true)]
+pub async fn verify_receipt(receipt_id: String) -> Result<ReceiptVerification, String> {
+    let receipt = find_receipt(&receipt_id).await?;
+    verify_found(&receipt).await
+}
+
+async fn verify_found(receipt: &Receipt) -> Result<ReceiptVerification, String> {
+    let leaf_hash = hex::encode(receipt_leaf(receipt));
+    let computed_root = hex::encode(merkle::compute_root_encoded(&leaf_hash, &receipt.merkle_proof)?);
+    let batch = match BATCH_ROOTS.with(|r| r.borrow().get(&computed_root)) {
+        Some(seq) => batch_at(seq).await?,
+        None => None,
+    };
+    let required = scheduler::state().policy.required_confirmations;
+    let final_event = events::final_event(&receipt.id);
+    
+    Ok(ReceiptVerification {
+        receipt_id: receipt.id.clone(),
+        leaf_hash,
+        computed_root,
+        root_matches_batch: batch.is_some(),
+        anchor_final: batch.as_ref().map(|b| b.anchor_status.is_final(required)).unwrap_or(false),
+        anchor_mode: batch.as_ref().and_then(|b| b.anchor_mode),
+        anchor_status: batch.map(|b| b.anchor_status),
+        final_event,
+    })
+}
+
+/// Answers every gateway route with an upgrade, so pages are served by
+/// `http_request_update` with a consensus-certified response; see `http`.
+#[query]
+pub fn http_request(request: HttpRequest) -> HttpResponse {
+    match http::route(&request.method, &request.url, &request.headers) {
+        Ok(_) => http::upgrade(),
+        Err(response) => response,
+    }
+}
+
+#[update]
+pub async fn http_request_update(request: HttpUpdateRequest) -> HttpResponse {
+    let route = match http::route(&request.method, &request.url, &request.headers) {
+        Ok(route) => route,
+        Err(response) => return response,
+    };
+    serve(route).await.unwrap_or_else(|e| http::text(404, &e))
+}
+
+async fn serve(route: Route) -> Result<HttpResponse, String> {
+    match route {
+        Route::Receipt { id, format } => {
+            let receipt = find_receipt(&id).await?;
+            let verification = verify_found(&receipt).await?;
+            Ok(http::receipt(&receipt, &verification, format))
+        }
+        Route::Batch { root, format } => {
+            let not_found = || format!("Batch {} not found", root);
+            let seq = BATCH_ROOTS.with(|r| r.borrow().get(&root)).ok_or_else(not_found)?;
+            let batch = batch_at(seq).await?.ok_or_else(not_found)?;
+            let required = scheduler::state().policy.required_confirmations;
+            let anchor_final = batch.anchor_status.is_final(required);
+            Ok(http::batch(&batch.summary(seq), anchor_final, archive::archive_of(seq), format))
+        }
+        Route::Ots { id } => Ok(http::download(&format!("{}.ots", id), "application/octet-stream", export_ots(id)?)),
+        Route::Bundle { id, cbor: false } => {
+            let json = export_proof_bundle(id.clone())?.json.into_bytes();
+            Ok(http::download(&format!("{}.json", id), "application/json", json))
+        }
+        Route::Bundle { id, cbor: true } => {
+            let cbor = export_proof_bundle(id.clone())?.cbor;
+            Ok(http::download(&format!("{}.cbor", id), "application/cbor", cbor))
+        }
+    }
 }
 
 #[query]
-pub fn get_receipt(receipt_id: String) -> Option<Receipt> {
-    RECEIPTS.with(|r| r.borrow().get(&receipt_id).cloned())
+pub fn verify_proof(leaf: String, proof: Vec<String>, root: String) -> Result<bool, String> {
+    let computed = merkle::compute_root_encoded(&leaf, &proof)?;
+    Ok(computed == merkle::decode_hash(&root)?)
 }
 
+// Returns every batch with its receipts; prefer `list_batches` once there are many
 #[query]
 pub fn get_batches() -> Vec<MerkleBatch> {
-    BATCHES.with(|b| b.borrow().clone())
+    BATCHES.with(|b| b.borrow().iter().map(|(_, batch)| batch).collect())
+}
+
+#[query]
+pub fn list_batches(offset: u64, limit: u64) -> Vec<BatchSummary> {
+    BATCHES.with(|b| {
+        b.borrow()
+            .range(offset..)
+            .take(limit.min(index::MAX_PAGE_SIZE) as usize)
+            .map(|(seq, batch)| batch.summary(seq))
+            .collect()
+    })
+}
+
+#[query]
+pub fn get_batch_by_root(root: String) -> Option<MerkleBatch> {
+    let seq = BATCH_ROOTS.with(|r| r.borrow().get(&root))?;
+    BATCHES.with(|b| b.borrow().get(&seq))
+}
+
+#[query]
+pub fn get_batch_for_receipt(receipt_id: String) -> Option<BatchSummary> {
+    let seq = RECEIPT_BATCH.with(|r| r.borrow().get(&receipt_id))?;
+    BATCHES.with(|b| b.borrow().get(&seq)).map(|batch| batch.summary(seq))
+}
+
+#[query]
+pub fn find_receipts_by_data_hash(data_hash: String, offset: u64, limit: u64) -> ReceiptPage {
+    receipt_page(index::by_data_hash(&data_hash, offset, limit))
+}
+
+// Key that signs batch roots; see `MerkleBatch::signature`
+#[update]
+pub async fn get_signing_public_key() -> Result<SigningKey, String> {
+    signing::public_key(signing::key_name(config::get().network)).await
+}
+
+#[query]
+pub fn get_mmr_state() -> MmrState {
+    mmr::state()
+}
+
+// Proves a batch root into the MMR of `mmr_size` leaves (default: current size)
+#[query]
+pub fn get_mmr_proof(batch_root: String, mmr_size: Option<u64>) -> Result<MmrInclusionProof, String> {
+    let seq = BATCH_ROOTS
+        .with(|r| r.borrow().get(&batch_root))
+        .ok_or_else(|| format!("Batch {} not found", batch_root))?;
+    mmr::inclusion_proof(seq, mmr_size.unwrap_or_else(mmr::size))
+}
+
+// Shows the MMR at `new_size` leaves extends the one at `old_size`, e.g.
+// between the states committed by two anchors
+#[query]
+pub fn get_mmr_consistency_proof(old_size: u64, new_size: u64) -> Result<MmrConsistencyProof, String> {
+    mmr::consistency_proof(old_size, new_size)
+}
+
+#[query]
+pub fn verify_mmr_consistency(proof: MmrConsistencyProof, old_root: String, new_root: String) -> Result<(), String> {
+    mmr::verify_consistency(&proof, &merkle::decode_hash(&old_root)?, &merkle::decode_hash(&new_root)?)
 }
 
 #[query]
 pub fn get_pending_count() -> usize {
-    PENDING_RECEIPTS.with(|p| p.borrow().len())
+    PENDING_RECEIPTS.with(|p| p.borrow().len() as usize)
+}
+
+#[query]
+pub fn get_config() -> CanisterConfig {
+    config::get()
+}
+
+#[update]
+pub fn set_config(update: ConfigUpdate) -> Result<CanisterConfig, String> {
+    access::require(Role::Admin)?;
+    config::update(update)
+}
+
+#[query]
+pub fn get_batch_policy() -> BatchPolicy {
+    scheduler::state().policy
+}
+
+#[update]
+pub fn set_batch_policy(policy: BatchPolicy) -> Result<(), String> {
+    access::require(Role::Admin)?;
+    policy.validate()?;
+    let current = scheduler::state().policy;
+    let interval_changed = current.check_interval_secs != policy.check_interval_secs
+        || current.confirmation_poll_secs != policy.confirmation_poll_secs;
+    scheduler::update_state(|s| s.policy = policy);
+    if interval_changed {
+        scheduler::start();
+    }
+    Ok(())
+}
+
+#[update]
+pub fn pause_scheduler() -> Result<(), String> {
+    access::require(Role::Admin)?;
+    scheduler::update_state(|s| s.paused = true);
+    Ok(())
+}
+
+#[update]
+pub fn resume_scheduler() -> Result<(), String> {
+    access::require(Role::Admin)?;
+    scheduler::update_state(|s| s.paused = false);
+    Ok(())
+}
+
+#[query]
+pub fn get_scheduler_status() -> SchedulerStatus {
+    let state = scheduler::state();
+    SchedulerStatus {
+        policy: state.policy,
+        paused: state.paused,
+        last_anchor_at: state.last_anchor_at,
+        pending_count: get_pending_count() as u64,
+    }
+}
+
+#[update]
+pub fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    access::require_controller()?;
+    access::grant(principal, role)
+}
+
+#[update]
+pub fn revoke_role(principal: Principal, role: Role) -> Result<(), String> {
+    access::require_controller()?;
+    access::revoke(principal, role);
+    if role == Role::Subscriber {
+        // Losing the role ends the subscription it was granted for
+        let _ = notifications::unsubscribe(&principal);
+    }
+    Ok(())
+}
+
+#[query]
+pub fn get_roles(principal: Principal) -> Vec<Role> {
+    access::roles_of(&principal)
+}
+
+#[query]
+pub fn list_role_grants() -> Vec<RoleGrant> {
+    access::list()
 }
 
 // Export Candid interface
@@ -165,26 +1281,484 @@ ic_cdk::export_candid!();
 #[cfg(test)]
 mod tests {
     use super::*;
+    use data_hash::{HashAlgorithm, HashValue};
+    use futures::executor::block_on;
+
+    fn digest(seed: &str) -> DataHash {
+        DataHash { algorithm: HashAlgorithm::Sha256, value: HashValue::Bytes(Sha256::digest(seed).to_vec()) }
+    }
 
     #[test]
     fn issue_and_count_pending() {
         let before = get_pending_count();
-        let id = issue_receipt("deadbeef".to_string());
+        let id = issue_receipt(digest("deadbeef"), None).unwrap();
         assert!(id.starts_with("receipt_"));
+        assert_eq!(local_receipt(&id).unwrap().issuer, access::caller());
         let after = get_pending_count();
         assert_eq!(after, before + 1);
     }
 
+    #[test]
+    fn receipt_ids_are_unique_and_reproducible() {
+        let first = issue_receipt(digest("same"), None).unwrap();
+        let second = issue_receipt(digest("same"), None).unwrap();
+        assert_ne!(first, second);
+
+        let receipt = local_receipt(&second).unwrap();
+        let rederived = derive_receipt_id(&receipt.issuer, &receipt.data_hash, receipt.sequence);
+        assert_eq!(rederived, second);
+    }
+
+    #[test]
+    fn dedupe_rejects_repeated_hash_from_same_issuer() {
+        config::update(ConfigUpdate { dedupe_receipts: Some(true), ..Default::default() }).unwrap();
+        let first = issue_receipt(digest("dup"), None).unwrap();
+        assert_eq!(issue_receipt(digest("dup"), None), Err(IssueError::Duplicate { receipt_id: first }));
+        let junk = DataHash { algorithm: HashAlgorithm::Sha256, value: HashValue::Hex("junk".to_string()) };
+        assert!(matches!(issue_receipt(junk, None), Err(IssueError::InvalidHex(_))));
+    }
+
+    #[test]
+    fn metadata_is_indexed_and_committed_in_the_leaf() {
+        let metadata = ReceiptMetadata { iqube_id: Some("iq-7".to_string()), tags: vec!["kyc".to_string()], ..Default::default() };
+        let id = issue_receipt(digest("meta"), Some(metadata)).unwrap();
+        issue_receipt(digest("other"), None).unwrap();
+        let root = batch().unwrap();
+
+        let found = get_receipts_by_iqube("iq-7".to_string(), 0, 10).receipts;
+        assert_eq!(found.iter().map(|r| r.id.clone()).collect::<Vec<_>>(), vec![id.clone()]);
+        assert_eq!(get_receipts_by_issuer(access::caller(), 0, 10).receipts.len(), 2);
+        assert!(get_receipts_by_iqube("iq-".to_string(), 0, 10).receipts.is_empty());
+
+        let mut tampered = local_receipt(&id).unwrap();
+        assert_eq!(verify_proof(hex::encode(receipt_leaf(&tampered)), tampered.merkle_proof.clone(), root.clone()), Ok(true));
+        tampered.metadata.as_mut().unwrap().tags.push("admin".to_string());
+        assert_eq!(verify_proof(hex::encode(receipt_leaf(&tampered)), tampered.merkle_proof, root), Ok(false));
+    }
+
+    #[test]
+    fn batches_are_listed_and_found_by_root_and_receipt() {
+        let first = issue_receipt(digest("p1"), None).unwrap();
+        let first_root = batch().unwrap();
+        issue_receipt(digest("p2"), None).unwrap();
+        issue_receipt(digest("p2"), None).unwrap();
+        let second_root = batch().unwrap();
+
+        let page = list_batches(1, 10);
+        assert_eq!(page.len(), 1);
+        assert_eq!((page[0].root.clone(), page[0].receipt_count), (second_root.clone(), 2));
+        assert_eq!(get_batch_by_root(second_root).unwrap().receipts.len(), 2);
+        assert_eq!(get_batch_for_receipt(first).unwrap().root, first_root);
+
+        let hash = format!("0x{}", hex::encode(Sha256::digest("p2")).to_uppercase());
+        assert_eq!(find_receipts_by_data_hash(hash, 0, 10).receipts.len(), 2);
+    }
+
+    #[test]
+    fn exported_bundle_verifies_offline() {
+        let id = issue_receipt(digest("bundle"), Some(ReceiptMetadata { iqube_id: Some("iq-1".to_string()), ..Default::default() })).unwrap();
+        assert!(export_proof_bundle(id.clone()).is_err());
+        issue_receipt(digest("bundle-2"), None).unwrap();
+        batch().unwrap();
+
+        let exported = export_proof_bundle(id).unwrap();
+        let network = config::get().network;
+        assert_eq!(bundle::verify_json(&exported.json, network), Ok(bundle::BundleVerdict::Committed));
+        assert_eq!(bundle::verify_cbor(&exported.cbor, network), Ok(bundle::BundleVerdict::Committed));
+    }
+
     #[test]
     fn batch_and_anchor_mock() {
+        config::apply_init_args(InitArgs { mode: Some(AnchorMode::Mock), ..Default::default() }).unwrap();
         // Ensure at least one receipt exists
-        let _ = issue_receipt("cafebabe".to_string());
-        let root = batch();
+        issue_receipt(digest("cafebabe"), None).unwrap();
+        let root = batch().unwrap();
         assert!(!root.is_empty());
         let batches = get_batches();
         assert!(!batches.is_empty());
-        // anchor() is async but uses mock values; should succeed
-        let res = futures::executor::block_on(anchor());
-        assert!(res.contains("Anchored batch") || res == "No batches to anchor");
+        // Mock mode never calls the signer; the batch is flagged as mock-anchored
+        let res = block_on(anchor()).unwrap();
+        assert!(res.contains("Anchored batch"));
+        let batch = get_batches().pop().unwrap();
+        assert_eq!(batch.anchor_mode, Some(AnchorMode::Mock));
+    }
+
+    #[test]
+    fn anchors_commit_to_mmr_states_that_extend_each_other() {
+        config::apply_init_args(InitArgs { mode: Some(AnchorMode::Mock), ..Default::default() }).unwrap();
+        issue_receipt(digest("m1"), None).unwrap();
+        let first = batch().unwrap();
+        issue_receipt(digest("m2"), None).unwrap();
+        let second = batch().unwrap();
+        block_on(anchor_all()).unwrap();
+
+        let old = get_batch_by_root(first.clone()).unwrap();
+        let new = get_batch_by_root(second).unwrap();
+        assert_eq!((old.mmr_size, new.mmr_size), (1, 2));
+        let new_root = get_mmr_state().root;
+        assert_eq!(new.anchor_status.txid(), Some(format!("mock_btc_txid_{}", &new_root[..8]).as_str()));
+
+        let old_root = hex::encode(mmr::root_at(1).unwrap());
+        let proof = get_mmr_consistency_proof(1, 2).unwrap();
+        assert_eq!(verify_mmr_consistency(proof.clone(), old_root.clone(), new_root.clone()), Ok(()));
+        assert!(verify_mmr_consistency(proof, new_root.clone(), old_root).is_err());
+
+        let inclusion = get_mmr_proof(first, None).unwrap();
+        let first_hash = merkle::decode_hash(&old.root).unwrap();
+        assert_eq!(proof_of_state_verifier::mmr::inclusion_root(&first_hash, &inclusion).map(hex::encode), Ok(new_root));
+    }
+
+    #[test]
+    fn new_batches_wait_for_a_threshold_signature() {
+        issue_receipt(digest("s1"), None).unwrap();
+        let root = batch().unwrap();
+        assert!(has_unsigned_batches());
+        // There is no replica on the host, so signing fails and the batch stays queued
+        block_on(sign_queued());
+        assert!(has_unsigned_batches());
+        assert_eq!(get_batch_by_root(root).unwrap().signature, None);
+        assert!(!SIGNING_ACTIVE.with(|a| a.get()));
+
+        // A batch that can never be signed leaves the queue without wedging signing
+        BATCHES.with(|b| {
+            let mut batches = b.borrow_mut();
+            let mut batch = batches.get(&0).unwrap();
+            batch.root = "not a hash".to_string();
+            batches.insert(0, batch);
+        });
+        block_on(sign_queued());
+        assert!(!has_unsigned_batches());
+        assert!(!SIGNING_ACTIVE.with(|a| a.get()));
+    }
+
+    #[test]
+    fn enabled_chain_targets_get_their_own_anchors() {
+        let target = |chain, enabled| AnchorTarget { chain, enabled };
+        // Nothing can publish to other chains yet, so Live deployments cannot enable them
+        assert!(set_anchor_target(target(Chain::Solana, true)).unwrap_err().contains("Mock mode"));
+        config::apply_init_args(InitArgs { mode: Some(AnchorMode::Mock), ..Default::default() }).unwrap();
+        set_anchor_target(target(Chain::Evm { chain_id: 137 }, true)).unwrap();
+        set_anchor_target(target(Chain::Solana, false)).unwrap();
+        assert!(set_anchor_target(target(Chain::Evm { chain_id: 0 }, true)).is_err());
+
+        issue_receipt(digest("c1"), None).unwrap();
+        let root = batch().unwrap();
+        let results = anchor_chains().unwrap();
+        assert_eq!(results.len(), 1);
+
+        let batch = get_batch_by_root(root).unwrap();
+        let anchors = batch.chain_anchors;
+        assert_eq!(anchors.len(), 1);
+        assert_eq!(anchors[0].chain, Chain::Evm { chain_id: 137 });
+        assert_eq!(anchors[0].mode, Some(AnchorMode::Mock));
+        assert!(anchors[0].status.txid().unwrap().starts_with("mock_evm:137_txid_"));
+        // The BTC anchor is untouched
+        assert_eq!(batch.anchor_status, AnchorStatus::Unanchored);
+        assert!(anchor_chains().unwrap().is_empty());
+    }
+
+    #[test]
+    fn burns_are_one_way_audited_and_committed() {
+        let id = issue_receipt(digest("burn"), None).unwrap();
+        batch().unwrap();
+        let burn = |receipt: &str, message: &str| {
+            block_on(burn_receipt(receipt.to_string(), message.to_string()))
+        };
+        // Burns are refused until the quorum source is configured
+        assert!(burn(&id, "msg_1").is_err());
+        config::update(ConfigUpdate { cross_chain_service: Some(Some(Principal::anonymous())), ..Default::default() }).unwrap();
+
+        assert!(burn("receipt_missing", "msg_1").is_err());
+        HOST_QUORUM_REACHED.with(|q| q.set(false));
+        assert!(burn(&id, "msg_1").unwrap_err().contains("has not reached DVN quorum"));
+        assert!(get_receipt_events(id.clone()).is_empty());
+        HOST_QUORUM_REACHED.with(|q| q.set(true));
+        let state = burn(&id, "msg_1").unwrap();
+        assert!(state.burned);
+        assert!(burn(&id, "msg_2").unwrap_err().contains("already burned"));
+        let other = issue_receipt(digest("burn-2"), None).unwrap();
+        assert!(burn(&other, "msg_1").unwrap_err().contains("already burned receipt"));
+        let unburn = set_burn_state(id.clone(), "msg_1".to_string(), false);
+        assert!(block_on(unburn).is_err());
+
+        let history = get_receipt_events(id.clone());
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].kind, ReceiptEventKind::Burned { message_id: "msg_1".to_string() });
+        assert_eq!(verify_receipt_event(history[0].seq), Ok(false));
+
+        // The next batch commits the pending receipt and the burn event together
+        let root = batch().unwrap();
+        assert_eq!(get_batch_by_root(root.clone()).unwrap().events, vec![history[0].seq]);
+        assert_eq!(get_receipt_events(id)[0].batch_root, Some(root));
+        assert_eq!(verify_receipt_event(history[0].seq), Ok(true));
+        assert!(block_on(verify_receipt(other)).unwrap().root_matches_batch);
+    }
+
+    #[test]
+    fn revocations_and_supersessions_are_committed_and_final() {
+        let revoked = issue_receipt(digest("revoke"), None).unwrap();
+        let metadata = ReceiptMetadata { iqube_id: Some("iqube-7".to_string()), ..Default::default() };
+        let issuer = Principal::from_slice(&[7; 29]);
+        let old = issue(issuer, digest("supersede"), Some(metadata.clone())).unwrap();
+        batch().unwrap();
+
+        assert!(block_on(revoke_receipt(revoked.clone(), String::new())).is_err());
+        let event = block_on(revoke_receipt(revoked.clone(), "issued in error".to_string())).unwrap();
+        assert_eq!(event.actor, Principal::anonymous());
+        assert!(block_on(revoke_receipt(revoked.clone(), "again".to_string())).unwrap_err().contains("already revoked"));
+        assert!(block_on(supersede_receipt(revoked.clone(), digest("other"))).is_err());
+
+        assert!(block_on(supersede_receipt(old.clone(), digest("supersede"))).is_err());
+        let junk = DataHash { algorithm: HashAlgorithm::Sha256, value: HashValue::Hex("junk".to_string()) };
+        assert!(block_on(supersede_receipt(old.clone(), junk)).unwrap_err().starts_with("Data hash is not valid hex"));
+        // An admin supersedes on the original issuer's behalf
+        let new = block_on(supersede_receipt(old.clone(), digest("supersede-v2"))).unwrap();
+        let superseding = local_receipt(&new).unwrap();
+        assert_eq!((superseding.issuer, superseding.metadata), (issuer, Some(metadata)));
+        assert!(block_on(supersede_receipt(old.clone(), digest("supersede-v3"))).unwrap_err().contains(&new));
+        assert!(block_on(revoke_receipt(old.clone(), "late".to_string())).is_err());
+
+        // Verifying the old proofs reveals what happened, once committed
+        let root = batch().unwrap();
+        for id in [&revoked, &old] {
+            let verification = block_on(verify_receipt(id.clone())).unwrap();
+            assert!(verification.root_matches_batch);
+            let event = verification.final_event.unwrap();
+            assert_eq!(event.batch_root.as_ref(), Some(&root));
+            assert_eq!(verify_receipt_event(event.seq), Ok(true));
+        }
+        assert_eq!(
+            block_on(verify_receipt(old)).unwrap().final_event.unwrap().kind,
+            ReceiptEventKind::Superseded { new_receipt_id: new.clone() }
+        );
+        assert!(block_on(verify_receipt(new)).unwrap().final_event.is_none());
+    }
+
+    // Stands in for a BTC anchor that reached final depth and a signature that landed
+    fn settle(root: &str) {
+        let seq = BATCH_ROOTS.with(|r| r.borrow().get(&root.to_string())).unwrap();
+        BATCHES.with(|b| {
+            let mut batch = b.borrow().get(&seq).unwrap();
+            batch.anchor_status = AnchorStatus::Confirmed { txid: "ab".repeat(32), block_height: 800_000, confirmations: 6 };
+            b.borrow_mut().insert(seq, batch);
+        });
+        ANCHOR_QUEUE.with(|q| q.borrow_mut().remove(&seq));
+        SIGNING_QUEUE.with(|q| q.borrow_mut().remove(&seq));
+    }
+
+    #[test]
+    fn settled_batches_move_to_archives_and_stay_verifiable() {
+        let policy = config::ArchivePolicy { archive_after_secs: 0, batches_per_archive: 1, archive_cycles: 1 };
+        config::update(ConfigUpdate { archive_policy: Some(Some(policy)), ..Default::default() }).unwrap();
+        let id = issue_receipt(digest("archive-1"), None).unwrap();
+        let first = batch().unwrap();
+        issue_receipt(digest("archive-2"), None).unwrap();
+        let second = batch().unwrap();
+        issue_receipt(digest("archive-3"), None).unwrap();
+        let unsettled = batch().unwrap();
+        settle(&first);
+        settle(&second);
+
+        assert!(block_on(archive_batches()).unwrap_err().contains("wasm"));
+        assert!(set_archive_wasm(b"not wasm".to_vec()).is_err());
+        set_archive_wasm(b"\0asm\x01\0\0\0".to_vec()).unwrap();
+        assert_eq!(block_on(archive_batches()), Ok(2));
+        assert_eq!(block_on(archive_batches()), Ok(0));
+
+        // One batch per archive under this policy
+        let archives = get_archives();
+        assert_eq!(archives.len(), 2);
+        assert_eq!((archives[0].start_seq, archives[0].batch_count), (Some(0), 1));
+        assert_eq!(locate_batch(first.clone()).unwrap().archive, Some(archives[0].canister_id));
+        assert_eq!(locate_batch(unsettled.clone()).unwrap().archive, None);
+        assert!(get_batch_by_root(first.clone()).is_none());
+        assert!(get_batch_by_root(unsettled).is_some());
+        // Archived receipts and batches stay out of the certified tree after an upgrade
+        let certified = certification::root_hash();
+        certification::rebuild();
+        assert_eq!(certification::root_hash(), certified);
+
+        // Lookups follow the index to the archive
+        assert!(local_receipt(&id).err().unwrap().contains("archived"));
+        let page = find_receipts_by_data_hash(hex::encode(Sha256::digest("archive-1")), 0, 10);
+        assert!(page.receipts.is_empty());
+        assert_eq!(page.archived, vec![ArchivedReceipt { receipt_id: id.clone(), archive: archives[0].canister_id }]);
+        assert_eq!(block_on(get_receipt(id.clone())).unwrap().id, id);
+        let verdict = block_on(verify_receipt(id.clone())).unwrap();
+        assert!(verdict.root_matches_batch && verdict.anchor_final);
+        assert_eq!(verdict.computed_root, first);
+        block_on(revoke_receipt(id.clone(), "archived but wrong".to_string())).unwrap();
+        assert!(block_on(verify_receipt(id)).unwrap().final_event.is_some());
+    }
+
+    #[test]
+    fn batches_cut_after_archiving_get_fresh_seqs() {
+        let policy = config::ArchivePolicy { archive_after_secs: 0, batches_per_archive: 10, archive_cycles: 1 };
+        config::update(ConfigUpdate { archive_policy: Some(Some(policy)), ..Default::default() }).unwrap();
+        set_archive_wasm(b"\0asm\x01\0\0\0".to_vec()).unwrap();
+        issue_receipt(digest("seq-1"), None).unwrap();
+        let first = batch().unwrap();
+        issue_receipt(digest("seq-2"), None).unwrap();
+        let second = batch().unwrap();
+        settle(&first);
+        assert_eq!(block_on(archive_batches()), Ok(1));
+
+        issue_receipt(digest("seq-3"), None).unwrap();
+        let third = batch().unwrap();
+        let archive = get_archives()[0].canister_id;
+        let first_location = locate_batch(first).unwrap();
+        assert_eq!((first_location.seq, first_location.archive), (0, Some(archive)));
+        assert_eq!(locate_batch(second.clone()).unwrap().seq, 1);
+        assert_eq!(locate_batch(third.clone()).unwrap().seq, 2);
+        assert_eq!(locate_batch(third.clone()).unwrap().archive, None);
+        // The still-local batch was not overwritten
+        assert_eq!(BATCHES.with(|b| b.borrow().get(&1)).unwrap().root, second);
+        assert_eq!(BATCHES.with(|b| b.borrow().get(&2)).unwrap().root, third);
+    }
+
+    #[test]
+    fn subscribers_are_notified_of_batches_anchors_and_burns() {
+        config::apply_init_args(InitArgs {
+            mode: Some(AnchorMode::Mock),
+            cross_chain_service: Some(Some(Principal::anonymous())),
+            ..Default::default()
+        })
+        .unwrap();
+        // Host callers are never canisters
+        assert!(subscribe("on_event".to_string(), None).is_err());
+        let subscriber = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 7, 1, 1]);
+        grant_role(subscriber, Role::Subscriber).unwrap();
+        notifications::subscribe(subscriber, "on_event".to_string(), None, now()).unwrap();
+
+        let id = issue_receipt(digest("notify"), None).unwrap();
+        let root = batch().unwrap();
+        block_on(anchor_batch(root.clone())).unwrap();
+        block_on(burn_receipt(id.clone(), "msg_notify".to_string())).unwrap();
+
+        // Host one-way calls always fail, so every delivery is waiting for a retry
+        let delivered: Vec<Notification> =
+            get_notification_queue().into_iter().map(|delivery| delivery.envelope.notification).collect();
+        assert_eq!(delivered.len(), 3);
+        assert_eq!(delivered[0], Notification::BatchCreated { seq: 0, root: root.clone(), receipt_count: 1 });
+        assert!(matches!(&delivered[1], Notification::AnchorBroadcast { chain: None, txid, .. } if txid.starts_with("mock_")));
+        assert!(matches!(&delivered[2], Notification::BurnStateChanged { receipt_id, burned: true, .. } if *receipt_id == id));
+
+        revoke_role(subscriber, Role::Subscriber).unwrap();
+        assert!(get_subscriptions().is_empty());
+    }
+
+    #[test]
+    fn gateway_serves_receipts_batches_and_proofs() {
+        config::apply_init_args(InitArgs { mode: Some(AnchorMode::Mock), ..Default::default() }).unwrap();
+        let get = |url: &str, html: bool| {
+            let headers = if html { vec![("Accept".to_string(), "text/html".to_string())] } else { vec![] };
+            let query = http_request(HttpRequest {
+                method: "GET".to_string(),
+                url: url.to_string(),
+                headers: headers.clone(),
+                body: vec![],
+                certificate_version: None,
+            });
+            assert_eq!(query.upgrade, Some(true), "{}", url);
+            let request = HttpUpdateRequest { method: "GET".to_string(), url: url.to_string(), headers, body: vec![] };
+            block_on(http_request_update(request))
+        };
+        let id = issue_receipt(digest("gateway"), None).unwrap();
+        let pending: serde_json::Value = serde_json::from_slice(&get(&format!("/receipt/{}", id), false).body).unwrap();
+        assert_eq!(pending["status"], "pending");
+
+        let root = batch().unwrap();
+        block_on(anchor_batch(root.clone())).unwrap();
+        let committed: serde_json::Value = serde_json::from_slice(&get(&format!("/receipt/{}.json", id), true).body).unwrap();
+        assert_eq!((committed["status"].as_str(), committed["batch_root"].as_str()), (Some("committed"), Some(root.as_str())));
+        assert_eq!(committed["anchor"]["mock"], true);
+
+        let page = get(&format!("/receipt/{}", id), true);
+        assert!(page.headers.contains(&("Content-Type".to_string(), "text/html; charset=utf-8".to_string())));
+        assert!(String::from_utf8(page.body).unwrap().contains(&format!("/batch/{}", root)));
+        let batch: serde_json::Value = serde_json::from_slice(&get(&format!("/batch/{}", root), false).body).unwrap();
+        assert_eq!(batch["receipt_count"], 1);
+
+        let bundle = get(&format!("/proof/{}.json", id), false);
+        assert_eq!(bundle.status_code, 200);
+        assert!(bundle::verify_json(std::str::from_utf8(&bundle.body).unwrap(), config::get().network).is_ok());
+        // Mock anchors never reach final depth, so there is no .ots proof
+        assert_eq!(get(&format!("/proof/{}.ots", id), false).status_code, 404);
+        assert_eq!(get("/receipt/receipt_404", false).status_code, 404);
+    }
+
+    #[test]
+    fn every_mutation_is_logged_as_an_icrc3_block() {
+        config::apply_init_args(InitArgs {
+            mode: Some(AnchorMode::Mock),
+            cross_chain_service: Some(Some(Principal::anonymous())),
+            ..Default::default()
+        })
+        .unwrap();
+        let id = issue_receipt(digest("logged"), None).unwrap();
+        let root = batch().unwrap();
+        block_on(anchor_batch(root)).unwrap();
+        block_on(burn_receipt(id, "msg_logged".to_string())).unwrap();
+
+        let all = icrc3::GetBlocksArgs { start: candid::Nat::from(0u64), length: candid::Nat::from(10u64) };
+        let blocks = icrc3_get_blocks(vec![all]).blocks;
+        let btypes: Vec<icrc3::Value> = blocks
+            .iter()
+            .map(|b| match &b.block {
+                icrc3::Value::Map(fields) => fields.iter().find(|(key, _)| key == "btype").unwrap().1.clone(),
+                _ => panic!("block is not a map"),
+            })
+            .collect();
+        let expected = ["iqube.receipt", "iqube.batch", "iqube.anchor", "iqube.burn"];
+        assert_eq!(btypes, expected.map(|btype| icrc3::Value::Text(btype.to_string())));
+
+        // The certified root covers the tip, and survives a rebuild
+        let root = certification::root_hash();
+        certification::rebuild();
+        assert_eq!(certification::root_hash(), root);
+        assert!(icrc3_get_archives(icrc3::GetArchivesArgs { from: None }).is_empty());
+    }
+
+    #[test]
+    fn anchor_all_drains_every_queued_batch() {
+        config::apply_init_args(InitArgs { mode: Some(AnchorMode::Mock), ..Default::default() }).unwrap();
+        issue_receipt(digest("01"), None).unwrap();
+        let first = batch().unwrap();
+        issue_receipt(digest("02"), None).unwrap();
+        batch().unwrap();
+        assert_eq!(get_anchor_queue().len(), 2);
+
+        let results = block_on(anchor_all()).unwrap();
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert!(get_anchor_queue().is_empty());
+        // A broadcast batch is never picked up again, by the queue or by hand
+        assert!(block_on(anchor_all()).unwrap().is_empty());
+        let txid = get_batch_by_root(first.clone()).unwrap().anchor_status.txid().map(str::to_string);
+        assert!(block_on(anchor_batch(first.clone())).unwrap_err().contains("already anchored"));
+        assert!(block_on(anchor()).unwrap_err().contains("already anchored"));
+        let status = get_batch_by_root(first).unwrap().anchor_status;
+        assert!(matches!(status, AnchorStatus::Broadcast { .. }));
+        assert_eq!(status.txid().map(str::to_string), txid);
+    }
+
+    #[test]
+    fn batch_writes_inclusion_proofs() {
+        let ids: Vec<String> = ["aa", "bb", "cc"].iter().map(|h| issue_receipt(digest(h), None).unwrap()).collect();
+        let root = batch().unwrap();
+        for id in ids {
+            let verdict = block_on(verify_receipt(id.clone())).unwrap();
+            assert!(verdict.root_matches_batch);
+            assert_eq!(verdict.computed_root, root);
+            let receipt = local_receipt(&id).unwrap();
+            assert_eq!(verify_proof(verdict.leaf_hash, receipt.merkle_proof, root.clone()), Ok(true));
+        }
+    }
+
+    #[test]
+    fn pending_receipt_does_not_verify() {
+        let id = issue_receipt(digest("dd"), None).unwrap();
+        let verdict = block_on(verify_receipt(id)).unwrap();
+        assert!(!verdict.root_matches_batch);
+        assert!(block_on(verify_receipt("missing".to_string())).is_err());
     }
 }
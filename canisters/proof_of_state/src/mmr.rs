@@ -0,0 +1,192 @@
+//! Merkle Mountain Range over all batch roots.
+//!
+//! This module stores the nodes, one leaf per batch, and builds inclusion and
+//! consistency proofs. Node addressing, the root formula and the proof checks
+//! are specified in [`proof_of_state_verifier::mmr`].
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+use crate::merkle::{self, Hash};
+use crate::storage::{self, Memory};
+use proof_of_state_verifier::mmr::{peak_positions, peak_slot};
+
+pub use proof_of_state_verifier::mmr::{bag, verify_consistency, MmrConsistencyProof, MmrInclusionProof};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MmrState {
+    pub size: u64,
+    pub root: String,
+    pub peaks: Vec<String>,
+}
+
+thread_local! {
+    static NODES: RefCell<StableBTreeMap<u64, Hash, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::MMR_NODES_MEMORY)));
+    static SIZE: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::MMR_SIZE_MEMORY), 0).expect("failed to init MMR size")
+    );
+}
+
+fn key(height: u32, index: u64) -> u64 {
+    ((height as u64) << 56) | index
+}
+
+fn node(height: u32, index: u64) -> Hash {
+    NODES.with(|n| n.borrow().get(&key(height, index))).expect("MMR node missing")
+}
+
+pub fn size() -> u64 {
+    SIZE.with(|s| *s.borrow().get())
+}
+
+/// Appends a batch root as the next leaf and returns the new size.
+pub fn append(batch_root: Hash) -> u64 {
+    let index = size();
+    let mut hash = merkle::leaf_hash(&batch_root);
+    let (mut height, mut position) = (0u32, index);
+    NODES.with(|n| {
+        let mut nodes = n.borrow_mut();
+        nodes.insert(key(height, position), hash);
+        while position & 1 == 1 {
+            let left = nodes.get(&key(height, position - 1)).expect("MMR node missing");
+            hash = merkle::node_hash(&left, &hash);
+            height += 1;
+            position >>= 1;
+            nodes.insert(key(height, position), hash);
+        }
+    });
+    SIZE.with(|s| s.borrow_mut().set(index + 1).expect("failed to store MMR size"));
+    index + 1
+}
+
+fn peaks_at(size: u64) -> Vec<Hash> {
+    peak_positions(size).into_iter().map(|(h, i)| node(h, i)).collect()
+}
+
+pub fn root_at(size: u64) -> Result<Hash, String> {
+    check_size(size)?;
+    Ok(bag(&peaks_at(size), size))
+}
+
+pub fn state() -> MmrState {
+    let size = size();
+    let peaks = peaks_at(size);
+    MmrState { size, root: hex::encode(bag(&peaks, size)), peaks: peaks.iter().map(hex::encode).collect() }
+}
+
+fn check_size(size: u64) -> Result<(), String> {
+    if size > self::size() {
+        return Err(format!("MMR has only {} leaves", self::size()));
+    }
+    Ok(())
+}
+
+// Siblings from (height, index) up to `target_height`
+fn path(mut height: u32, mut index: u64, target_height: u32) -> Vec<String> {
+    let mut siblings = Vec::new();
+    while height < target_height {
+        siblings.push(hex::encode(node(height, index ^ 1)));
+        height += 1;
+        index >>= 1;
+    }
+    siblings
+}
+
+pub fn inclusion_proof(leaf_index: u64, mmr_size: u64) -> Result<MmrInclusionProof, String> {
+    check_size(mmr_size)?;
+    if leaf_index >= mmr_size {
+        return Err(format!("Leaf {} is not within the first {} leaves", leaf_index, mmr_size));
+    }
+    let (height, _) = peak_positions(mmr_size)[peak_slot(leaf_index, mmr_size)];
+    Ok(MmrInclusionProof {
+        leaf_index,
+        mmr_size,
+        siblings: path(0, leaf_index, height),
+        peaks: peaks_at(mmr_size).iter().map(hex::encode).collect(),
+    })
+}
+
+pub fn consistency_proof(old_size: u64, new_size: u64) -> Result<MmrConsistencyProof, String> {
+    check_size(new_size)?;
+    if old_size == 0 || old_size > new_size {
+        return Err("Consistency needs 0 < old_size <= new_size".to_string());
+    }
+    let new_positions = peak_positions(new_size);
+    let paths = peak_positions(old_size)
+        .into_iter()
+        .map(|(h, i)| {
+            let (target, _) = new_positions
+                .iter()
+                .find(|(nh, ni)| *nh >= h && (i << h) >> nh == *ni)
+                .expect("old peaks stay inside new peaks");
+            path(h, i, *target)
+        })
+        .collect();
+    Ok(MmrConsistencyProof {
+        old_size,
+        new_size,
+        old_peaks: peaks_at(old_size).iter().map(hex::encode).collect(),
+        new_peaks: peaks_at(new_size).iter().map(hex::encode).collect(),
+        paths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proof_of_state_verifier::mmr::inclusion_root;
+
+    fn build(leaves: u64) -> Vec<Hash> {
+        let roots: Vec<Hash> = (0..leaves).map(|i| merkle::leaf_hash(&i.to_be_bytes())).collect();
+        for root in &roots {
+            append(*root);
+        }
+        roots
+    }
+
+    #[test]
+    fn every_batch_proves_into_every_later_root() {
+        let roots = build(9);
+        for size in 1..=9u64 {
+            let root = root_at(size).unwrap();
+            for (leaf, batch_root) in roots.iter().enumerate().take(size as usize) {
+                let proof = inclusion_proof(leaf as u64, size).unwrap();
+                assert_eq!(inclusion_root(batch_root, &proof), Ok(root), "leaf {} size {}", leaf, size);
+            }
+        }
+        let proof = inclusion_proof(2, 5).unwrap();
+        assert!(inclusion_root(&roots[3], &proof).is_err());
+        assert!(inclusion_proof(0, 10).is_err());
+    }
+
+    #[test]
+    fn consistency_holds_between_all_sizes() {
+        build(9);
+        for old in 1..=9u64 {
+            for new in old..=9u64 {
+                let proof = consistency_proof(old, new).unwrap();
+                let verdict = verify_consistency(&proof, &root_at(old).unwrap(), &root_at(new).unwrap());
+                assert_eq!(verdict, Ok(()), "{} -> {}", old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn rewritten_history_fails_consistency() {
+        build(4);
+        let old_root = root_at(3).unwrap();
+        let proof = consistency_proof(3, 4).unwrap();
+
+        // A different history of the same length bags to a different root
+        let mut forged = proof.clone();
+        forged.old_peaks[1] = hex::encode([9u8; 32]);
+        assert!(verify_consistency(&forged, &old_root, &root_at(4).unwrap()).is_err());
+
+        // Swapping the old peaks' order changes where they must land
+        let mut reordered = proof;
+        reordered.paths.swap(0, 1);
+        assert!(verify_consistency(&reordered, &old_root, &root_at(4).unwrap()).is_err());
+    }
+}
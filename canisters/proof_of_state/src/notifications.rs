@@ -0,0 +1,289 @@
+//! Push notifications to subscriber canisters.
+//!
+//! A canister granted the `Subscriber` role subscribes by naming one of its own
+//! methods, optionally limited to some notification kinds. Every batch cut,
+//! anchor broadcast, anchor reaching final depth and burn is then delivered to
+//! it as a one-way call
+//!
+//! ```text
+//! <method> : (NotificationEnvelope) -> () oneway;
+//! ```
+//!
+//! so a slow or trapping subscriber can never hold up proof_of_state. Delivery
+//! is best-effort: a one-way call only fails when it cannot be enqueued, and
+//! such deliveries wait in a bounded retry queue with the anchor backoff,
+//! oldest dropped first when it is full, until `MAX_ATTEMPTS` is reached.
+//! Envelope IDs count up from zero per subscription and only advance for
+//! notifications it selected, so a subscriber can spot gaps and fall back to
+//! polling. Host tests cannot make calls, so there every delivery is queued.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::anchoring::AnchorRetry;
+use crate::chains::Chain;
+use crate::storage::{self, Memory};
+
+pub const MAX_SUBSCRIBERS: u64 = 32;
+const MAX_METHOD_LEN: usize = 128;
+const MAX_QUEUED: u64 = 1000;
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationKind {
+    BatchCreated,
+    AnchorBroadcast,
+    AnchorConfirmed,
+    BurnStateChanged,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Notification {
+    BatchCreated { seq: u64, root: String, receipt_count: u64 },
+    // `chain` is None for the BTC anchor
+    AnchorBroadcast { seq: u64, root: String, chain: Option<Chain>, txid: String },
+    AnchorConfirmed { seq: u64, root: String, chain: Option<Chain>, txid: String },
+    BurnStateChanged { receipt_id: String, message_id: String, burned: bool, event_seq: u64 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct NotificationEnvelope {
+    pub id: u64,
+    pub timestamp: u64,
+    pub notification: Notification,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Subscription {
+    pub subscriber: Principal,
+    pub method: String,
+    // None subscribes to every kind
+    pub kinds: Option<Vec<NotificationKind>>,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct QueuedDelivery {
+    pub subscriber: Principal,
+    pub envelope: NotificationEnvelope,
+    pub retry: AnchorRetry,
+}
+
+thread_local! {
+    static SUBSCRIPTIONS: RefCell<StableBTreeMap<Principal, Subscription, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::SUBSCRIPTIONS_MEMORY)));
+    // Deliveries that could not be enqueued, oldest first
+    static QUEUE: RefCell<StableBTreeMap<u64, QueuedDelivery, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::NOTIFICATION_QUEUE_MEMORY)));
+    // Next envelope ID per subscriber
+    static NEXT_IDS: RefCell<StableBTreeMap<Principal, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::NOTIFICATION_COUNTERS_MEMORY)));
+}
+
+impl Notification {
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            Notification::BatchCreated { .. } => NotificationKind::BatchCreated,
+            Notification::AnchorBroadcast { .. } => NotificationKind::AnchorBroadcast,
+            Notification::AnchorConfirmed { .. } => NotificationKind::AnchorConfirmed,
+            Notification::BurnStateChanged { .. } => NotificationKind::BurnStateChanged,
+        }
+    }
+}
+
+impl Subscription {
+    fn wants(&self, kind: NotificationKind) -> bool {
+        self.kinds.as_ref().is_none_or(|kinds| kinds.contains(&kind))
+    }
+}
+
+// Canister IDs are opaque principals, whose last byte is 0x01
+fn is_canister(principal: &Principal) -> bool {
+    principal.as_slice().last() == Some(&0x01)
+}
+
+pub fn subscribe(
+    subscriber: Principal,
+    method: String,
+    kinds: Option<Vec<NotificationKind>>,
+    now: u64,
+) -> Result<Subscription, String> {
+    if !is_canister(&subscriber) {
+        return Err(format!("Only canisters can subscribe, not {}", subscriber));
+    }
+    if method.is_empty() || method.len() > MAX_METHOD_LEN {
+        return Err(format!("method must be 1-{} bytes", MAX_METHOD_LEN));
+    }
+    if kinds.as_ref().is_some_and(|kinds| kinds.is_empty()) {
+        return Err("kinds must not be empty; omit it to receive everything".to_string());
+    }
+    SUBSCRIPTIONS.with(|s| {
+        let mut subscriptions = s.borrow_mut();
+        if !subscriptions.contains_key(&subscriber) && subscriptions.len() >= MAX_SUBSCRIBERS {
+            return Err(format!("At most {} subscribers are allowed", MAX_SUBSCRIBERS));
+        }
+        let subscription = Subscription { subscriber, method, kinds, created_at: now };
+        subscriptions.insert(subscriber, subscription.clone());
+        Ok(subscription)
+    })
+}
+
+pub fn unsubscribe(subscriber: &Principal) -> Result<(), String> {
+    NEXT_IDS.with(|n| n.borrow_mut().remove(subscriber));
+    SUBSCRIPTIONS
+        .with(|s| s.borrow_mut().remove(subscriber))
+        .map(|_| ())
+        .ok_or_else(|| format!("{} is not subscribed", subscriber))
+}
+
+pub fn subscriptions() -> Vec<Subscription> {
+    SUBSCRIPTIONS.with(|s| s.borrow().iter().map(|(_, subscription)| subscription).collect())
+}
+
+pub fn queued() -> Vec<QueuedDelivery> {
+    QUEUE.with(|q| q.borrow().iter().map(|(_, delivery)| delivery).collect())
+}
+
+fn next_id(subscriber: &Principal) -> u64 {
+    NEXT_IDS.with(|n| {
+        let mut counters = n.borrow_mut();
+        let id = counters.get(subscriber).unwrap_or(0);
+        counters.insert(*subscriber, id + 1);
+        id
+    })
+}
+
+fn send(subscription: &Subscription, envelope: &NotificationEnvelope) -> Result<(), String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::notify(subscription.subscriber, &subscription.method, (envelope,))
+            .map_err(|code| format!("Notification to {} failed: {:?}", subscription.subscriber, code))
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (subscription, envelope);
+        Err("One-way calls are only available on a replica".to_string())
+    }
+}
+
+fn enqueue(delivery: QueuedDelivery) {
+    QUEUE.with(|q| {
+        let mut queue = q.borrow_mut();
+        while queue.len() >= MAX_QUEUED {
+            queue.pop_first();
+        }
+        let key = queue.last_key_value().map(|(key, _)| key + 1).unwrap_or(0);
+        queue.insert(key, delivery);
+    });
+}
+
+/// Sends the notification to every interested subscriber, queueing failures.
+pub fn publish(notification: Notification, now: u64) {
+    let kind = notification.kind();
+    for subscription in subscriptions().into_iter().filter(|s| s.wants(kind)) {
+        let id = next_id(&subscription.subscriber);
+        let envelope = NotificationEnvelope { id, timestamp: now, notification: notification.clone() };
+        if let Err(e) = send(&subscription, &envelope) {
+            let retry = AnchorRetry::new(now).failed(e, now);
+            enqueue(QueuedDelivery { subscriber: subscription.subscriber, envelope, retry });
+        }
+    }
+}
+
+pub fn has_due(now: u64) -> bool {
+    QUEUE.with(|q| q.borrow().iter().any(|(_, delivery)| delivery.retry.is_due(now)))
+}
+
+/// Retries every due delivery once; deliveries to unsubscribed canisters and
+/// those out of attempts are dropped.
+pub fn retry_due(now: u64) {
+    let due: Vec<(u64, QueuedDelivery)> =
+        QUEUE.with(|q| q.borrow().iter().filter(|(_, delivery)| delivery.retry.is_due(now)).collect());
+    for (key, delivery) in due {
+        let subscription = SUBSCRIPTIONS.with(|s| s.borrow().get(&delivery.subscriber));
+        let result = match &subscription {
+            Some(subscription) => send(subscription, &delivery.envelope),
+            None => Ok(()),
+        };
+        QUEUE.with(|q| {
+            let mut queue = q.borrow_mut();
+            match result {
+                Err(e) if delivery.retry.attempts < MAX_ATTEMPTS => {
+                    queue.insert(key, QueuedDelivery { retry: delivery.retry.failed(e, now), ..delivery });
+                }
+                _ => {
+                    queue.remove(&key);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canister(id: u8) -> Principal {
+        Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, id, 1, 1])
+    }
+
+    #[test]
+    fn failed_deliveries_are_bounded_and_retried() {
+        assert!(subscribe(Principal::anonymous(), "on_event".to_string(), None, 0).is_err());
+        assert!(subscribe(canister(1), String::new(), None, 0).is_err());
+        subscribe(canister(1), "on_event".to_string(), None, 0).unwrap();
+        subscribe(canister(2), "on_burn".to_string(), Some(vec![NotificationKind::BurnStateChanged]), 0).unwrap();
+
+        publish(Notification::BatchCreated { seq: 0, root: "00".repeat(32), receipt_count: 1 }, 0);
+        let first = queued();
+        assert_eq!(first.len(), 1);
+        assert_eq!((first[0].subscriber, first[0].envelope.id), (canister(1), 0));
+
+        // Retries back off, then give up
+        const DAY: u64 = 86_400 * 1_000_000_000;
+        assert!(!has_due(0));
+        retry_due(DAY);
+        assert_eq!(queued()[0].retry.attempts, 2);
+        for day in 2..=MAX_ATTEMPTS as u64 {
+            retry_due(day * DAY);
+        }
+        assert!(queued().is_empty());
+
+        for seq in 0..MAX_QUEUED + 5 {
+            publish(Notification::BatchCreated { seq, root: "00".repeat(32), receipt_count: 1 }, 0);
+        }
+        let backlog = queued();
+        assert_eq!(backlog.len() as u64, MAX_QUEUED);
+        assert!(matches!(backlog[0].envelope.notification, Notification::BatchCreated { seq: 5, .. }));
+
+        unsubscribe(&canister(1)).unwrap();
+        retry_due(DAY);
+        assert!(queued().is_empty());
+    }
+
+    #[test]
+    fn envelope_ids_count_per_subscriber() {
+        subscribe(canister(1), "on_event".to_string(), None, 0).unwrap();
+        subscribe(canister(2), "on_burn".to_string(), Some(vec![NotificationKind::BurnStateChanged]), 0).unwrap();
+        let burn = Notification::BurnStateChanged {
+            receipt_id: "receipt".to_string(),
+            message_id: "message".to_string(),
+            burned: true,
+            event_seq: 0,
+        };
+        publish(Notification::BatchCreated { seq: 0, root: "00".repeat(32), receipt_count: 1 }, 0);
+        publish(Notification::BatchCreated { seq: 1, root: "00".repeat(32), receipt_count: 1 }, 0);
+        publish(burn.clone(), 0);
+
+        // Kinds a subscriber filtered out leave no gaps in its IDs
+        let ids: Vec<(Principal, u64)> = queued().iter().map(|d| (d.subscriber, d.envelope.id)).collect();
+        assert_eq!(ids, vec![(canister(1), 0), (canister(1), 1), (canister(1), 2), (canister(2), 0)]);
+
+        // A new subscription starts again from zero
+        unsubscribe(&canister(2)).unwrap();
+        subscribe(canister(2), "on_burn".to_string(), None, 0).unwrap();
+        publish(burn, 0);
+        assert_eq!(queued().last().map(|d| (d.subscriber, d.envelope.id)), Some((canister(2), 0)));
+    }
+}
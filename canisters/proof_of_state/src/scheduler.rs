@@ -0,0 +1,190 @@
+//! Timer-driven batching and anchoring.
+//!
+//! A periodic tick cuts a batch once enough receipts are pending or the oldest
+//! one has waited too long, then anchors every queued batch whose retry is
+//! due, provided the minimum interval since the previous anchor has elapsed. A
+//! second timer polls broadcast anchors until they reach
+//! `required_confirmations`; it keeps running while the scheduler is paused so
+//! in-flight anchors still finalize. Anchors on other chains are broadcast as
+//! soon as their retry is due, without the BTC minimum interval. Batches whose
+//! threshold signature failed are retried on every tick, as are queued
+//! subscriber notifications, and settled batches past the archive threshold
+//! are moved to archives. The policy and pause flag live in stable memory; the
+//! timers themselves are re-armed on init and upgrade.
+
+use candid::{CandidType, Deserialize};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::storage::{self, Memory};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatchPolicy {
+    pub max_pending_receipts: u64,
+    pub max_receipt_age_secs: u64,
+    pub min_anchor_interval_secs: u64,
+    pub check_interval_secs: u64,
+    pub required_confirmations: u32,
+    pub confirmation_poll_secs: u64,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        BatchPolicy {
+            max_pending_receipts: 1000,
+            max_receipt_age_secs: 3600,
+            min_anchor_interval_secs: 3600,
+            check_interval_secs: 60,
+            required_confirmations: 6,
+            confirmation_poll_secs: 600,
+        }
+    }
+}
+
+impl BatchPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_pending_receipts == 0 {
+            return Err("max_pending_receipts must be greater than zero".to_string());
+        }
+        if self.check_interval_secs == 0 || self.confirmation_poll_secs == 0 {
+            return Err("Timer intervals must be greater than zero".to_string());
+        }
+        if self.required_confirmations == 0 {
+            return Err("required_confirmations must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn batch_due(&self, pending_count: u64, oldest_pending_at: Option<u64>, now: u64) -> bool {
+        if pending_count == 0 {
+            return false;
+        }
+        let too_old = oldest_pending_at
+            .map(|at| now.saturating_sub(at) >= self.max_receipt_age_secs * NANOS_PER_SEC)
+            .unwrap_or(false);
+        pending_count >= self.max_pending_receipts || too_old
+    }
+
+    pub fn anchor_due(&self, last_anchor_at: Option<u64>, now: u64) -> bool {
+        last_anchor_at
+            .map(|at| now.saturating_sub(at) >= self.min_anchor_interval_secs * NANOS_PER_SEC)
+            .unwrap_or(true)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct SchedulerState {
+    pub policy: BatchPolicy,
+    pub paused: bool,
+    pub last_anchor_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SchedulerStatus {
+    pub policy: BatchPolicy,
+    pub paused: bool,
+    pub last_anchor_at: Option<u64>,
+    pub pending_count: u64,
+}
+
+thread_local! {
+    static STATE: RefCell<StableCell<SchedulerState, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::SCHEDULER_MEMORY), SchedulerState::default())
+            .expect("failed to init scheduler state")
+    );
+    static TIMER_IDS: RefCell<Vec<TimerId>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn state() -> SchedulerState {
+    STATE.with(|s| s.borrow().get().clone())
+}
+
+pub fn update_state(f: impl FnOnce(&mut SchedulerState)) {
+    STATE.with(|s| {
+        let mut cell = s.borrow_mut();
+        let mut state = cell.get().clone();
+        f(&mut state);
+        cell.set(state).expect("failed to store scheduler state");
+    });
+}
+
+pub fn record_anchor(at: u64) {
+    update_state(|s| s.last_anchor_at = Some(at));
+}
+
+pub fn start() {
+    let policy = state().policy;
+    let timer_ids = vec![
+        ic_cdk_timers::set_timer_interval(Duration::from_secs(policy.check_interval_secs), tick),
+        ic_cdk_timers::set_timer_interval(Duration::from_secs(policy.confirmation_poll_secs), || {
+            ic_cdk::spawn(crate::poll_anchor_confirmations())
+        }),
+    ];
+    for previous in TIMER_IDS.with(|t| std::mem::replace(&mut *t.borrow_mut(), timer_ids)) {
+        ic_cdk_timers::clear_timer(previous);
+    }
+}
+
+fn tick() {
+    let state = state();
+    if state.paused {
+        return;
+    }
+    let now = crate::now();
+    if state.policy.batch_due(crate::pending_commitments(), crate::oldest_pending_timestamp(), now) {
+        crate::batch_pending();
+    }
+    if state.policy.anchor_due(state.last_anchor_at, now) && crate::has_due_anchors(now) {
+        ic_cdk::spawn(async {
+            for result in crate::anchor_queued(true).await {
+                ic_cdk::println!("Scheduled anchor: {:?}", result);
+            }
+        });
+    }
+    if crate::chains::has_due(now) {
+        for result in crate::chains::anchor_queued(true) {
+            ic_cdk::println!("Scheduled chain anchor: {:?}", result);
+        }
+    }
+    if crate::has_unsigned_batches() {
+        ic_cdk::spawn(crate::sign_queued());
+    }
+    if crate::notifications::has_due(now) {
+        crate::notifications::retry_due(now);
+    }
+    if crate::archive::has_due(now) {
+        ic_cdk::spawn(async {
+            if let Err(e) = crate::archive::archive_due().await {
+                ic_cdk::println!("Scheduled archiving failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_due_on_count_or_age() {
+        let policy = BatchPolicy { max_pending_receipts: 3, max_receipt_age_secs: 10, ..Default::default() };
+        let now = 100 * NANOS_PER_SEC;
+        assert!(!policy.batch_due(0, None, now));
+        assert!(!policy.batch_due(2, Some(95 * NANOS_PER_SEC), now));
+        assert!(policy.batch_due(3, Some(95 * NANOS_PER_SEC), now));
+        assert!(policy.batch_due(1, Some(90 * NANOS_PER_SEC), now));
+    }
+
+    #[test]
+    fn anchor_respects_min_interval() {
+        let policy = BatchPolicy { min_anchor_interval_secs: 60, ..Default::default() };
+        let now = 1000 * NANOS_PER_SEC;
+        assert!(policy.anchor_due(None, now));
+        assert!(!policy.anchor_due(Some(now - 30 * NANOS_PER_SEC), now));
+        assert!(policy.anchor_due(Some(now - 60 * NANOS_PER_SEC), now));
+    }
+}
@@ -0,0 +1,132 @@
+//! Threshold ECDSA attestations over batch roots.
+//!
+//! Every new batch is signed with a secp256k1 key the IC derives for this
+//! canister, so partners can check a root as soon as it is cut instead of
+//! waiting an hour or more for BTC confirmations. The signed digest is
+//!
+//! ```text
+//! sha256("iqube-batch-root-v1" || batch_root || created_at_be)
+//! ```
+//!
+//! and the signature is the 64-byte `r || s` returned by `sign_with_ecdsa`,
+//! checkable against `get_signing_public_key` with any secp256k1 library. The
+//! key follows the configured network: `key_1` on mainnet, `test_key_1` on
+//! testnet and `dfx_test_key` on a local replica. Signing needs a replica, so
+//! on the host it always fails and batches stay queued.
+
+use candid::{CandidType, Deserialize};
+use ic_cdk::api::management_canister::ecdsa::{EcdsaCurve, EcdsaKeyId};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::config::BtcNetwork;
+use crate::merkle::Hash;
+
+const DOMAIN: &[u8] = b"iqube-batch-root-v1";
+const DERIVATION_PATH: &[u8] = b"batch_roots";
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatchSignature {
+    pub key_name: String,
+    // Compact r || s over `message(root, created_at)`
+    pub signature: Vec<u8>,
+    pub signed_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SigningKey {
+    pub key_name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    // SEC1-compressed secp256k1 point
+    pub public_key: Vec<u8>,
+}
+
+thread_local! {
+    // Public keys never change for a key name, so they are fetched once per name
+    static PUBLIC_KEYS: RefCell<Vec<SigningKey>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn key_name(network: BtcNetwork) -> &'static str {
+    match network {
+        BtcNetwork::Mainnet => "key_1",
+        BtcNetwork::Testnet => "test_key_1",
+        BtcNetwork::Regtest => "dfx_test_key",
+    }
+}
+
+fn derivation_path() -> Vec<Vec<u8>> {
+    vec![DERIVATION_PATH.to_vec()]
+}
+
+fn key_id(key_name: &str) -> EcdsaKeyId {
+    EcdsaKeyId { curve: EcdsaCurve::Secp256k1, name: key_name.to_string() }
+}
+
+pub fn message(batch_root: &Hash, created_at: u64) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update(batch_root);
+    hasher.update(created_at.to_be_bytes());
+    hasher.finalize().into()
+}
+
+pub async fn sign(key_name: &str, message: Hash) -> Result<BatchSignature, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        use ic_cdk::api::management_canister::ecdsa::{sign_with_ecdsa, SignWithEcdsaArgument};
+        let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+            message_hash: message.to_vec(),
+            derivation_path: derivation_path(),
+            key_id: key_id(key_name),
+        })
+        .await
+        .map_err(|(code, msg)| format!("sign_with_ecdsa failed: {:?} - {}", code, msg))?;
+        Ok(BatchSignature { key_name: key_name.to_string(), signature: response.signature, signed_at: crate::now() })
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (key_id(key_name), derivation_path(), message);
+        Err("Threshold signing is only available on a replica".to_string())
+    }
+}
+
+pub async fn public_key(key_name: &str) -> Result<SigningKey, String> {
+    if let Some(key) = PUBLIC_KEYS.with(|k| k.borrow().iter().find(|k| k.key_name == key_name).cloned()) {
+        return Ok(key);
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        use ic_cdk::api::management_canister::ecdsa::{ecdsa_public_key, EcdsaPublicKeyArgument};
+        let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+            canister_id: None,
+            derivation_path: derivation_path(),
+            key_id: key_id(key_name),
+        })
+        .await
+        .map_err(|(code, msg)| format!("ecdsa_public_key failed: {:?} - {}", code, msg))?;
+        let key = SigningKey {
+            key_name: key_name.to_string(),
+            derivation_path: derivation_path(),
+            public_key: response.public_key,
+        };
+        PUBLIC_KEYS.with(|k| k.borrow_mut().push(key.clone()));
+        Ok(key)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Err("Threshold keys are only available on a replica".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_commits_to_root_and_timestamp() {
+        let root = [1u8; 32];
+        assert_eq!(message(&root, 5), message(&root, 5));
+        assert_ne!(message(&root, 5), message(&root, 6));
+        assert_ne!(message(&root, 5), message(&[2u8; 32], 5));
+    }
+}
@@ -0,0 +1,78 @@
+//! Stable-memory layout for proof_of_state.
+//!
+//! Every piece of canister state lives in its own virtual memory, so it
+//! survives `dfx deploy --mode upgrade`. The memory manager and Candid encoding
+//! come from `canister_storage`; this module numbers the memories and carries
+//! the migrations between schema versions.
+
+use canister_storage::{candid_storable, MemoryId};
+
+pub use canister_storage::{memory, Memory};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Memory IDs are part of the on-chain layout: never reuse or renumber them.
+// Memory 0 holds the schema version (`canister_storage::SCHEMA_MEMORY`).
+pub const RECEIPTS_MEMORY: MemoryId = MemoryId::new(1);
+pub const BATCHES_MEMORY: MemoryId = MemoryId::new(2);
+pub const PENDING_MEMORY: MemoryId = MemoryId::new(3);
+pub const BURN_STATES_MEMORY: MemoryId = MemoryId::new(4);
+pub const SCHEDULER_MEMORY: MemoryId = MemoryId::new(5);
+pub const TRACKED_ANCHORS_MEMORY: MemoryId = MemoryId::new(6);
+pub const CONFIG_MEMORY: MemoryId = MemoryId::new(7);
+pub const BATCH_ROOTS_MEMORY: MemoryId = MemoryId::new(8);
+pub const ANCHOR_QUEUE_MEMORY: MemoryId = MemoryId::new(9);
+pub const ROLES_MEMORY: MemoryId = MemoryId::new(10);
+pub const RECEIPT_COUNTER_MEMORY: MemoryId = MemoryId::new(11);
+pub const ISSUER_HASHES_MEMORY: MemoryId = MemoryId::new(12);
+pub const IQUBE_INDEX_MEMORY: MemoryId = MemoryId::new(13);
+pub const ISSUER_INDEX_MEMORY: MemoryId = MemoryId::new(14);
+pub const DATA_HASH_INDEX_MEMORY: MemoryId = MemoryId::new(15);
+pub const RECEIPT_BATCH_MEMORY: MemoryId = MemoryId::new(16);
+pub const ANCHOR_PROOFS_MEMORY: MemoryId = MemoryId::new(17);
+pub const MMR_NODES_MEMORY: MemoryId = MemoryId::new(18);
+pub const MMR_SIZE_MEMORY: MemoryId = MemoryId::new(19);
+pub const SIGNING_QUEUE_MEMORY: MemoryId = MemoryId::new(20);
+pub const ANCHOR_TARGETS_MEMORY: MemoryId = MemoryId::new(21);
+pub const CHAIN_ANCHOR_QUEUE_MEMORY: MemoryId = MemoryId::new(22);
+pub const EVENTS_MEMORY: MemoryId = MemoryId::new(23);
+pub const PENDING_EVENTS_MEMORY: MemoryId = MemoryId::new(24);
+pub const RECEIPT_EVENTS_MEMORY: MemoryId = MemoryId::new(25);
+pub const BURN_MESSAGES_MEMORY: MemoryId = MemoryId::new(26);
+pub const ARCHIVES_MEMORY: MemoryId = MemoryId::new(27);
+pub const ARCHIVED_BATCHES_MEMORY: MemoryId = MemoryId::new(28);
+pub const ARCHIVE_WASM_MEMORY: MemoryId = MemoryId::new(29);
+pub const SUBSCRIPTIONS_MEMORY: MemoryId = MemoryId::new(30);
+pub const NOTIFICATION_QUEUE_MEMORY: MemoryId = MemoryId::new(31);
+pub const NOTIFICATION_COUNTERS_MEMORY: MemoryId = MemoryId::new(32);
+pub const BLOCKS_MEMORY: MemoryId = MemoryId::new(33);
+pub const CERTIFIED_LEAVES_MEMORY: MemoryId = MemoryId::new(34);
+
+pub fn init_schema() {
+    canister_storage::set_schema_version(SCHEMA_VERSION);
+}
+
+pub fn migrate() {
+    let migrated = canister_storage::migrate(SCHEMA_VERSION, |from| match from {
+        // Heap-only state from before stable storage is gone after the
+        // upgrade, so there is nothing to carry over.
+        0 => {}
+        _ => unreachable!("no migration from schema v{}", from),
+    });
+    migrated.unwrap_or_else(|e| ic_cdk::trap(&e));
+}
+
+candid_storable!(
+    crate::MerkleBatch,
+    crate::BurnState,
+    crate::scheduler::SchedulerState,
+    crate::config::CanisterConfig,
+    crate::anchoring::AnchorRetry,
+    crate::access::RoleSet,
+    crate::chains::AnchorTarget,
+    crate::events::ReceiptEvent,
+    crate::archive::ArchiveInfo,
+    crate::notifications::Subscription,
+    crate::notifications::QueuedDelivery,
+    crate::icrc3::Value,
+);
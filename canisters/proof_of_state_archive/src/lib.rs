@@ -0,0 +1,158 @@
+//! Archive for old proof_of_state batches.
+//!
+//! proof_of_state spawns archives and moves confirmed batches older than its
+//! archive threshold into them, ICRC-3 style: the parent keeps only a root
+//! index and the archive that holds each batch, and redirects lookups here.
+//! Only the parent that installed an archive may append to it.
+//!
+//! Batches, receipts and SPV proofs are stored as the Candid encoding the
+//! parent produced, so they decode with the `MerkleBatch`, `Receipt` and
+//! `AnchorProof` types of proof_of_state.did and their commitments stay
+//! byte-for-byte what was anchored.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::{init, post_upgrade, query, update};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+mod storage;
+
+use storage::Memory;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatchRecord {
+    pub seq: u64,
+    pub root: String,
+    // Candid-encoded MerkleBatch
+    pub batch: Vec<u8>,
+    // Candid-encoded AnchorProof of the batch's final BTC anchor
+    pub anchor_proof: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReceiptRecord {
+    pub id: String,
+    pub seq: u64,
+    // Candid-encoded Receipt
+    pub receipt: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ArchiveInitArgs {
+    pub parent: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchiveStats {
+    pub parent: Principal,
+    pub batch_count: u64,
+    pub receipt_count: u64,
+    pub first_seq: Option<u64>,
+    pub last_seq: Option<u64>,
+}
+
+thread_local! {
+    static PARENT: RefCell<StableCell<Principal, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::PARENT_MEMORY), Principal::anonymous())
+            .expect("failed to init parent cell")
+    );
+    static BATCHES: RefCell<StableBTreeMap<u64, BatchRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::BATCHES_MEMORY)));
+    static ROOTS: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::ROOTS_MEMORY)));
+    static RECEIPTS: RefCell<StableBTreeMap<String, ReceiptRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(storage::memory(storage::RECEIPTS_MEMORY)));
+}
+
+fn parent() -> Principal {
+    PARENT.with(|p| *p.borrow().get())
+}
+
+#[init]
+fn init(args: ArchiveInitArgs) {
+    storage::init_schema();
+    PARENT.with(|p| p.borrow_mut().set(args.parent).expect("failed to store parent"));
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    storage::migrate();
+}
+
+// Re-appending a batch overwrites it, so the parent can safely retry
+fn append(caller: Principal, batch: BatchRecord, receipts: Vec<ReceiptRecord>) -> Result<(), String> {
+    if caller != parent() {
+        return Err(format!("Caller {} is not this archive's parent", caller));
+    }
+    if let Some(receipt) = receipts.iter().find(|r| r.seq != batch.seq) {
+        return Err(format!("Receipt {} does not belong to batch {}", receipt.id, batch.seq));
+    }
+    RECEIPTS.with(|r| {
+        let mut stored = r.borrow_mut();
+        for receipt in receipts {
+            stored.insert(receipt.id.clone(), receipt);
+        }
+    });
+    ROOTS.with(|r| r.borrow_mut().insert(batch.root.clone(), batch.seq));
+    BATCHES.with(|b| b.borrow_mut().insert(batch.seq, batch));
+    Ok(())
+}
+
+#[update]
+fn append_batch(batch: BatchRecord, receipts: Vec<ReceiptRecord>) -> Result<(), String> {
+    append(ic_cdk::caller(), batch, receipts)
+}
+
+#[query]
+fn get_batch(seq: u64) -> Option<BatchRecord> {
+    BATCHES.with(|b| b.borrow().get(&seq))
+}
+
+#[query]
+fn get_batch_by_root(root: String) -> Option<BatchRecord> {
+    ROOTS.with(|r| r.borrow().get(&root)).and_then(get_batch)
+}
+
+#[query]
+fn get_receipt(receipt_id: String) -> Option<ReceiptRecord> {
+    RECEIPTS.with(|r| r.borrow().get(&receipt_id))
+}
+
+#[query]
+fn get_archive_stats() -> ArchiveStats {
+    BATCHES.with(|b| {
+        let batches = b.borrow();
+        ArchiveStats {
+            parent: parent(),
+            batch_count: batches.len(),
+            receipt_count: RECEIPTS.with(|r| r.borrow().len()),
+            first_seq: batches.first_key_value().map(|(seq, _)| seq),
+            last_seq: batches.last_key_value().map(|(seq, _)| seq),
+        }
+    })
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_parent_appends() {
+        let batch = BatchRecord { seq: 3, root: "ab".repeat(32), batch: vec![1], anchor_proof: None };
+        let receipt = ReceiptRecord { id: "receipt_1".to_string(), seq: 3, receipt: vec![2] };
+
+        let stranger = Principal::from_slice(&[1; 29]);
+        assert!(append(stranger, batch.clone(), vec![receipt.clone()]).is_err());
+        let stray = ReceiptRecord { seq: 4, ..receipt.clone() };
+        assert!(append(parent(), batch.clone(), vec![stray]).is_err());
+
+        append(parent(), batch.clone(), vec![receipt.clone()]).unwrap();
+        append(parent(), batch.clone(), vec![receipt.clone()]).unwrap();
+        assert_eq!(get_batch_by_root(batch.root.clone()), Some(batch));
+        assert_eq!(get_receipt(receipt.id.clone()), Some(receipt));
+        let stats = get_archive_stats();
+        assert_eq!((stats.batch_count, stats.receipt_count, stats.last_seq), (1, 1, Some(3)));
+    }
+}
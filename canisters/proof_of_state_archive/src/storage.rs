@@ -0,0 +1,33 @@
+//! Stable-memory layout for proof_of_state_archive.
+//!
+//! Archived records live in stable memory so they survive `dfx deploy --mode
+//! upgrade`. The memory manager and Candid encoding come from
+//! `canister_storage`.
+
+use canister_storage::MemoryId;
+
+pub use canister_storage::{memory, Memory};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Memory IDs are part of the on-chain layout: never reuse or renumber them.
+// Memory 0 holds the schema version (`canister_storage::SCHEMA_MEMORY`).
+pub const PARENT_MEMORY: MemoryId = MemoryId::new(1);
+pub const BATCHES_MEMORY: MemoryId = MemoryId::new(2);
+pub const ROOTS_MEMORY: MemoryId = MemoryId::new(3);
+pub const RECEIPTS_MEMORY: MemoryId = MemoryId::new(4);
+
+pub fn init_schema() {
+    canister_storage::set_schema_version(SCHEMA_VERSION);
+}
+
+pub fn migrate() {
+    let migrated = canister_storage::migrate(SCHEMA_VERSION, |from| match from {
+        // Archives always start with a schema recorded at install
+        0 => {}
+        _ => unreachable!("no migration from schema v{}", from),
+    });
+    migrated.unwrap_or_else(|e| ic_cdk::trap(&e));
+}
+
+canister_storage::candid_storable!(crate::BatchRecord, crate::ReceiptRecord);
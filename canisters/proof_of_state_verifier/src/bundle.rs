@@ -0,0 +1,431 @@
+//! Self-contained proof bundles and their offline verifier.
+//!
+//! A bundle carries everything needed to check a receipt without reaching the
+//! canister: the receipt itself (so the leaf can be recomputed), its Merkle
+//! path, the batch root and, once the anchor is final, the raw BTC transaction
+//! with an SPV proof of its inclusion in a block. Verification walks that
+//! chain end to end:
+//!
+//! 1. the receipt hashes to `leaf_hash`, and the Merkle path leads to `batch_root`;
+//! 2. `mmr_proof` leads from `batch_root` to the MMR root the anchor commits to;
+//! 3. the raw transaction hashes to `anchor_txid` and pays an `OP_RETURN <root>`;
+//! 4. the SPV path leads from the txid to the header's Merkle root;
+//! 5. the header hashes to `block_hash` and meets its own proof-of-work
+//!    target, which may be no easier than the network's proof-of-work limit.
+//!
+//! Step 5 only bounds how cheap a forged header is: a single header at the
+//! minimum difficulty can still be mined on ordinary hardware, and
+//! `block_height` is carried as claimed. A verifier has to check `block_hash`
+//! (and its height) against a header chain it trusts before relying on an
+//! `Anchored` verdict.
+//!
+//! Bundles are exported as JSON and as CBOR; both decode to the same `ProofBundle`.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::merkle::{self, Hash};
+use crate::mmr::{self, MmrInclusionProof};
+use crate::{AnchorProof, BtcNetwork, Receipt};
+
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProofBundle {
+    pub version: u32,
+    pub receipt: Receipt,
+    pub leaf_hash: String,
+    pub batch_root: String,
+    pub anchor_txid: Option<String>,
+    // The txid was fabricated by a Mock-mode canister and never reached BTC
+    pub anchor_mock: bool,
+    pub anchor_proof: Option<AnchorProof>,
+    pub mmr_proof: MmrInclusionProof,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BundleVerdict {
+    /// The receipt is committed in the batch root, but the bundle has no BTC proof.
+    Committed,
+    /// The batch root is in a BTC transaction included in the given block.
+    /// Neither the block hash nor the height is checked against the chain.
+    Anchored { block_height: u64, block_hash: String },
+}
+
+#[derive(candid::CandidType, Deserialize, Clone)]
+pub struct ExportedBundle {
+    pub json: String,
+    pub cbor: Vec<u8>,
+}
+
+impl ProofBundle {
+    pub fn export(&self) -> Result<ExportedBundle, String> {
+        Ok(ExportedBundle {
+            json: serde_json::to_string_pretty(self).map_err(|e| format!("Failed to encode bundle: {}", e))?,
+            cbor: serde_cbor::to_vec(self).map_err(|e| format!("Failed to encode bundle: {}", e))?,
+        })
+    }
+}
+
+/// Easiest proof-of-work target, in compact form, a header on `network` may declare.
+pub fn pow_limit(network: BtcNetwork) -> u32 {
+    match network {
+        BtcNetwork::Mainnet | BtcNetwork::Testnet => 0x1d00_ffff,
+        BtcNetwork::Regtest => 0x207f_ffff,
+    }
+}
+
+pub fn verify_json(json: &str, network: BtcNetwork) -> Result<BundleVerdict, String> {
+    let bundle: ProofBundle = serde_json::from_str(json).map_err(|e| format!("Invalid bundle JSON: {}", e))?;
+    verify(&bundle, network)
+}
+
+pub fn verify_cbor(cbor: &[u8], network: BtcNetwork) -> Result<BundleVerdict, String> {
+    let bundle: ProofBundle = serde_cbor::from_slice(cbor).map_err(|e| format!("Invalid bundle CBOR: {}", e))?;
+    verify(&bundle, network)
+}
+
+pub fn verify(bundle: &ProofBundle, network: BtcNetwork) -> Result<BundleVerdict, String> {
+    if bundle.version != BUNDLE_VERSION {
+        return Err(format!("Unsupported bundle version {}", bundle.version));
+    }
+    if bundle.receipt.leaf_version != crate::metadata::LEAF_VERSION {
+        return Err(format!("Unsupported leaf version {}", bundle.receipt.leaf_version));
+    }
+
+    let leaf = crate::receipt_leaf(&bundle.receipt);
+    if hex::encode(leaf) != bundle.leaf_hash {
+        return Err("Receipt does not hash to the bundled leaf".to_string());
+    }
+    let root = merkle::compute_root_encoded(&bundle.leaf_hash, &bundle.receipt.merkle_proof)?;
+    if root != merkle::decode_hash(&bundle.batch_root)? {
+        return Err("Merkle path does not lead to the batch root".to_string());
+    }
+    let anchored_root = mmr::inclusion_root(&root, &bundle.mmr_proof)?;
+
+    let Some(proof) = &bundle.anchor_proof else {
+        return Ok(BundleVerdict::Committed);
+    };
+    if bundle.anchor_txid.as_deref() != Some(proof.txid.as_str()) {
+        return Err("Anchor proof is for a different transaction".to_string());
+    }
+
+    let raw_tx = hex::decode(&proof.raw_tx).map_err(|e| format!("Invalid raw transaction hex: {}", e))?;
+    let tx = parse_transaction(&raw_tx)?;
+    if display_hex(&tx.txid) != proof.txid {
+        return Err("Raw transaction does not hash to the anchor txid".to_string());
+    }
+    let mut commitment = vec![0x6a, 0x20];
+    commitment.extend_from_slice(&anchored_root);
+    if !tx.output_scripts.contains(&commitment) {
+        return Err("Anchor transaction has no OP_RETURN with the anchored root".to_string());
+    }
+
+    let header = hex::decode(&proof.block_header).map_err(|e| format!("Invalid block header hex: {}", e))?;
+    if header.len() != 80 {
+        return Err("Block header must be 80 bytes".to_string());
+    }
+    let header_hash = double_sha256(&header);
+    if display_hex(&header_hash) != proof.block_hash {
+        return Err("Block header does not hash to the block hash".to_string());
+    }
+    let bits = u32::from_le_bytes(header[72..76].try_into().unwrap());
+    let (Some(claimed), Some(limit)) = (target(bits), target(pow_limit(network))) else {
+        return Err("Block header has an invalid proof-of-work target".to_string());
+    };
+    if claimed > limit {
+        return Err(format!("Block header target is easier than the {:?} proof-of-work limit", network));
+    }
+    if !meets_target(&header_hash, bits) {
+        return Err("Block header does not meet its proof-of-work target".to_string());
+    }
+
+    let mut node = tx.txid;
+    let mut index = proof.tx_index;
+    for sibling in &proof.merkle_path {
+        let mut sibling = merkle::decode_hash(sibling)?;
+        sibling.reverse();
+        let mut pair = Vec::with_capacity(64);
+        if index & 1 == 1 {
+            pair.extend_from_slice(&sibling);
+            pair.extend_from_slice(&node);
+        } else {
+            pair.extend_from_slice(&node);
+            pair.extend_from_slice(&sibling);
+        }
+        node = double_sha256(&pair);
+        index >>= 1;
+    }
+    if node[..] != header[36..68] {
+        return Err("SPV path does not lead to the block's Merkle root".to_string());
+    }
+
+    Ok(BundleVerdict::Anchored { block_height: proof.block_height, block_hash: proof.block_hash.clone() })
+}
+
+pub(crate) fn double_sha256(data: &[u8]) -> Hash {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+// Bitcoin shows hashes byte-reversed
+fn display_hex(hash: &Hash) -> String {
+    let mut reversed = *hash;
+    reversed.reverse();
+    hex::encode(reversed)
+}
+
+// Big-endian 256-bit target = mantissa * 256^(exponent - 3); None for zero,
+// negative or overflowing compact values
+fn target(bits: u32) -> Option<Hash> {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+    if mantissa == 0 || bits & 0x0080_0000 != 0 || exponent > 32 {
+        return None;
+    }
+    let mut target = [0u8; 32];
+    for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+        let position = 32 + i;
+        if position >= exponent && position - exponent < 32 {
+            target[position - exponent] = *byte;
+        }
+    }
+    Some(target)
+}
+
+fn meets_target(header_hash: &Hash, bits: u32) -> bool {
+    let mut value = *header_hash;
+    value.reverse();
+    target(bits).is_some_and(|target| value <= target)
+}
+
+pub(crate) struct ParsedTransaction {
+    // Serialization without witness data, which is what the txid commits to
+    pub(crate) stripped: Vec<u8>,
+    pub(crate) txid: Hash,
+    pub(crate) output_scripts: Vec<Vec<u8>>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| "Truncated transaction".to_string())?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> Result<usize, String> {
+        let value = match self.take(1)?[0] {
+            0xfd => u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            0xfe => u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            0xff => u64::from_le_bytes(self.take(8)?.try_into().unwrap()),
+            n => n as u64,
+        };
+        usize::try_from(value).map_err(|_| "Oversized length in transaction".to_string())
+    }
+}
+
+// Just enough of the transaction format to recover the txid (which excludes
+// witness data) and the output scripts.
+pub(crate) fn parse_transaction(raw: &[u8]) -> Result<ParsedTransaction, String> {
+    let mut reader = Reader { bytes: raw, pos: 0 };
+    reader.take(4)?;
+    let segwit = raw.get(4) == Some(&0) && raw.get(5) == Some(&1);
+    if segwit {
+        reader.take(2)?;
+    }
+    let body_start = reader.pos;
+
+    let inputs = reader.varint()?;
+    for _ in 0..inputs {
+        reader.take(36)?;
+        let script_len = reader.varint()?;
+        reader.take(script_len)?;
+        reader.take(4)?;
+    }
+    let outputs = reader.varint()?;
+    let mut output_scripts = Vec::with_capacity(outputs.min(16));
+    for _ in 0..outputs {
+        reader.take(8)?;
+        let script_len = reader.varint()?;
+        output_scripts.push(reader.take(script_len)?.to_vec());
+    }
+    let body_end = reader.pos;
+
+    if segwit {
+        for _ in 0..inputs {
+            let items = reader.varint()?;
+            for _ in 0..items {
+                let len = reader.varint()?;
+                reader.take(len)?;
+            }
+        }
+    }
+    let locktime = reader.take(4)?;
+    if reader.pos != raw.len() {
+        return Err("Trailing bytes after transaction".to_string());
+    }
+
+    let mut stripped = raw[..4].to_vec();
+    stripped.extend_from_slice(&raw[body_start..body_end]);
+    stripped.extend_from_slice(locktime);
+    Ok(ParsedTransaction { txid: double_sha256(&stripped), stripped, output_scripts })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::merkle::MerkleTree;
+
+    // One input, one OP_RETURN output committing to `root`
+    fn anchor_tx(root: &Hash, segwit: bool) -> Vec<u8> {
+        let mut tx = vec![2, 0, 0, 0];
+        if segwit {
+            tx.extend_from_slice(&[0, 1]);
+        }
+        tx.push(1);
+        tx.extend_from_slice(&[7; 36]);
+        tx.push(0);
+        tx.extend_from_slice(&[0xff; 4]);
+        tx.push(1);
+        tx.extend_from_slice(&[0; 8]);
+        tx.push(34);
+        tx.extend_from_slice(&[0x6a, 0x20]);
+        tx.extend_from_slice(root);
+        if segwit {
+            tx.extend_from_slice(&[1, 2, 0xaa, 0xbb]);
+        }
+        tx.extend_from_slice(&[0; 4]);
+        tx
+    }
+
+    // A two-leaf MMR whose second leaf is `batch_root`
+    fn sample_mmr_proof(batch_root: &Hash) -> MmrInclusionProof {
+        let earlier = merkle::leaf_hash(b"earlier batch");
+        let peak = merkle::node_hash(&merkle::leaf_hash(&earlier), &merkle::leaf_hash(batch_root));
+        MmrInclusionProof {
+            leaf_index: 1,
+            mmr_size: 2,
+            siblings: vec![hex::encode(merkle::leaf_hash(&earlier))],
+            peaks: vec![hex::encode(peak)],
+        }
+    }
+
+    pub(crate) fn sample_bundle() -> ProofBundle {
+        let receipt = Receipt {
+            id: "receipt_0_00".to_string(),
+            data_hash: "ab".repeat(32),
+            hash_algorithm: crate::data_hash::HashAlgorithm::Sha256,
+            timestamp: 1,
+            merkle_proof: vec![],
+            issuer: candid::Principal::anonymous(),
+            sequence: 0,
+            metadata: None,
+            leaf_version: crate::metadata::LEAF_VERSION,
+        };
+        let leaves = vec![crate::receipt_leaf(&receipt), merkle::leaf_hash(b"other")];
+        let tree = MerkleTree::build(leaves.clone());
+        let root = tree.root().unwrap();
+        let receipt = Receipt { merkle_proof: tree.proof(0).iter().map(|s| s.encode()).collect(), ..receipt };
+
+        let mmr_proof = sample_mmr_proof(&root);
+        let anchored_root = mmr::inclusion_root(&root, &mmr_proof).unwrap();
+
+        // The anchor is the second of two transactions in the block
+        let raw_tx = anchor_tx(&anchored_root, true);
+        let txid = parse_transaction(&raw_tx).unwrap().txid;
+        let coinbase = [5u8; 32];
+        let mut pair = coinbase.to_vec();
+        pair.extend_from_slice(&txid);
+        let merkle_root = double_sha256(&pair);
+
+        let mut header = vec![0u8; 80];
+        header[36..68].copy_from_slice(&merkle_root);
+        // Regtest difficulty: almost any hash qualifies
+        header[72..76].copy_from_slice(&0x207f_ffffu32.to_le_bytes());
+        let mut nonce = 0u32;
+        while !meets_target(&double_sha256(&header), 0x207f_ffff) {
+            nonce += 1;
+            header[76..80].copy_from_slice(&nonce.to_le_bytes());
+        }
+
+        ProofBundle {
+            version: BUNDLE_VERSION,
+            leaf_hash: hex::encode(leaves[0]),
+            receipt,
+            batch_root: hex::encode(root),
+            anchor_txid: Some(display_hex(&txid)),
+            anchor_mock: false,
+            anchor_proof: Some(AnchorProof {
+                txid: display_hex(&txid),
+                raw_tx: hex::encode(&raw_tx),
+                block_height: 7,
+                block_hash: display_hex(&double_sha256(&header)),
+                block_header: hex::encode(&header),
+                merkle_path: vec![display_hex(&coinbase)],
+                tx_index: 1,
+            }),
+            mmr_proof,
+        }
+    }
+
+    #[test]
+    fn anchored_bundle_verifies_in_both_encodings() {
+        let exported = sample_bundle().export().unwrap();
+        let expected = BundleVerdict::Anchored {
+            block_height: 7,
+            block_hash: sample_bundle().anchor_proof.unwrap().block_hash,
+        };
+        assert_eq!(verify_json(&exported.json, BtcNetwork::Regtest), Ok(expected));
+        assert_eq!(verify_cbor(&exported.cbor, BtcNetwork::Regtest), verify_json(&exported.json, BtcNetwork::Regtest));
+    }
+
+    #[test]
+    fn headers_easier_than_the_network_limit_are_rejected() {
+        // The fixture header declares regtest difficulty, which no real chain accepts
+        for network in [BtcNetwork::Mainnet, BtcNetwork::Testnet] {
+            assert!(verify(&sample_bundle(), network).unwrap_err().contains("proof-of-work limit"));
+        }
+        assert!(target(0x1d00_ffff) < target(0x207f_ffff));
+        assert!(target(0x1d80_ffff).is_none());
+    }
+
+    #[test]
+    fn tampering_anywhere_in_the_chain_is_detected() {
+        let mut receipt = sample_bundle();
+        receipt.receipt.data_hash = "cd".repeat(32);
+        assert!(verify(&receipt, BtcNetwork::Regtest).is_err());
+
+        let mut path = sample_bundle();
+        path.anchor_proof.as_mut().unwrap().tx_index = 0;
+        assert!(verify(&path, BtcNetwork::Regtest).is_err());
+
+        let mut tx = sample_bundle();
+        let proof = tx.anchor_proof.as_mut().unwrap();
+        proof.raw_tx = hex::encode(anchor_tx(&[0; 32], true));
+        assert!(verify(&tx, BtcNetwork::Regtest).is_err());
+
+        let mut wrong_peak = sample_bundle();
+        wrong_peak.mmr_proof.siblings[0] = hex::encode([0u8; 32]);
+        assert!(verify(&wrong_peak, BtcNetwork::Regtest).is_err());
+
+        let unanchored = ProofBundle { anchor_proof: None, ..sample_bundle() };
+        assert_eq!(verify(&unanchored, BtcNetwork::Regtest), Ok(BundleVerdict::Committed));
+        // Only the current bundle and leaf formats exist
+        let future = ProofBundle { version: BUNDLE_VERSION + 1, ..sample_bundle() };
+        assert!(verify(&future, BtcNetwork::Regtest).is_err());
+    }
+
+    #[test]
+    fn txid_ignores_witness_data() {
+        let root = [3u8; 32];
+        let legacy = parse_transaction(&anchor_tx(&root, false)).unwrap();
+        let segwit = parse_transaction(&anchor_tx(&root, true)).unwrap();
+        assert_eq!(legacy.txid, segwit.txid);
+    }
+}
@@ -0,0 +1,105 @@
+//! Validation of the content hashes receipts commit to.
+//!
+//! Callers hand in a 32-byte digest, either as raw bytes or hex, tagged with
+//! the algorithm that produced it. It is normalized to lowercase hex without a
+//! `0x` prefix before being stored, which is also the form the BTC anchor and
+//! the Merkle leaves expect.
+
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+pub const DIGEST_LEN: usize = 32;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Keccak256,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum HashValue {
+    Bytes(Vec<u8>),
+    Hex(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DataHash {
+    pub algorithm: HashAlgorithm,
+    pub value: HashValue,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum IssueError {
+    Unauthorized(String),
+    EmptyHash,
+    InvalidHex(String),
+    InvalidLength { expected: u32, actual: u32 },
+    Duplicate { receipt_id: String },
+    InvalidMetadata(String),
+}
+
+impl std::fmt::Display for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IssueError::Unauthorized(reason) | IssueError::InvalidMetadata(reason) => f.write_str(reason),
+            IssueError::EmptyHash => f.write_str("Data hash must not be empty"),
+            IssueError::InvalidHex(reason) => write!(f, "Data hash is not valid hex: {}", reason),
+            IssueError::InvalidLength { expected, actual } => {
+                write!(f, "Data hash must be {} bytes, got {}", expected, actual)
+            }
+            IssueError::Duplicate { receipt_id } => write!(f, "Data hash was already issued as {}", receipt_id),
+        }
+    }
+}
+
+impl DataHash {
+    /// Lowercase hex of the validated digest.
+    pub fn normalize(&self) -> Result<String, IssueError> {
+        let bytes = match &self.value {
+            HashValue::Bytes(bytes) => bytes.clone(),
+            HashValue::Hex(value) => {
+                let value = value.trim();
+                let value = value.strip_prefix("0x").unwrap_or(value);
+                if value.is_empty() {
+                    return Err(IssueError::EmptyHash);
+                }
+                hex::decode(value).map_err(|e| IssueError::InvalidHex(e.to_string()))?
+            }
+        };
+        if bytes.is_empty() {
+            return Err(IssueError::EmptyHash);
+        }
+        if bytes.len() != DIGEST_LEN {
+            return Err(IssueError::InvalidLength { expected: DIGEST_LEN as u32, actual: bytes.len() as u32 });
+        }
+        Ok(hex::encode(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_hash(value: &str) -> DataHash {
+        DataHash { algorithm: HashAlgorithm::Sha256, value: HashValue::Hex(value.to_string()) }
+    }
+
+    #[test]
+    fn hex_and_bytes_normalize_to_the_same_digest() {
+        let upper = format!("0x{}", "AB".repeat(32));
+        let from_hex = hex_hash(&upper).normalize().unwrap();
+        let from_bytes = DataHash { algorithm: HashAlgorithm::Keccak256, value: HashValue::Bytes(vec![0xab; 32]) };
+        assert_eq!(from_hex, "ab".repeat(32));
+        assert_eq!(from_bytes.normalize().unwrap(), from_hex);
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        assert_eq!(hex_hash("  ").normalize(), Err(IssueError::EmptyHash));
+        assert!(matches!(hex_hash("zz").normalize(), Err(IssueError::InvalidHex(_))));
+        assert_eq!(
+            hex_hash("deadbeef").normalize(),
+            Err(IssueError::InvalidLength { expected: 32, actual: 4 })
+        );
+    }
+}
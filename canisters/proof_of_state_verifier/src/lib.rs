@@ -0,0 +1,61 @@
+//! Offline verification of proof_of_state receipts.
+//!
+//! Holds everything a receipt's proofs depend on: the receipt record and its
+//! leaf commitment, batch Merkle paths, MMR proofs, proof bundles and `.ots`
+//! timestamps. The canister builds its proofs with the same code, and nothing
+//! here depends on ic-cdk, so off-chain tools can link this crate directly.
+
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+pub mod bundle;
+pub mod data_hash;
+pub mod merkle;
+pub mod metadata;
+pub mod mmr;
+pub mod ots;
+#[cfg(feature = "storable")]
+mod storable;
+
+use data_hash::HashAlgorithm;
+use metadata::ReceiptMetadata;
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct Receipt {
+    pub id: String,
+    // Lowercase hex digest
+    pub data_hash: String,
+    pub hash_algorithm: HashAlgorithm,
+    pub timestamp: u64,
+    pub merkle_proof: Vec<String>,
+    pub issuer: Principal,
+    // Issue counter the ID was derived from
+    pub sequence: u64,
+    pub metadata: Option<ReceiptMetadata>,
+    // Leaf commitment format, see `metadata::leaf_preimage`
+    pub leaf_version: u32,
+}
+
+// Mirrors btc_signer_psbt's AnchorProof record; hashes are in display order
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AnchorProof {
+    pub txid: String,
+    pub raw_tx: String,
+    pub block_height: u64,
+    pub block_hash: String,
+    pub block_header: String,
+    pub merkle_path: Vec<String>,
+    pub tx_index: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BtcNetwork {
+    Mainnet,
+    #[default]
+    Testnet,
+    Regtest,
+}
+
+pub fn receipt_leaf(receipt: &Receipt) -> merkle::Hash {
+    merkle::leaf_hash(&metadata::leaf_preimage(receipt))
+}
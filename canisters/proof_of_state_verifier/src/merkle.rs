@@ -0,0 +1,166 @@
+//! Binary Merkle tree over receipt leaves.
+//!
+//! Hashing is domain separated so a leaf can never be confused with an inner
+//! node: `leaf = sha256(0x00 || data)`, `node = sha256(0x01 || left || right)`.
+//! When a level has an odd number of nodes, the last node is promoted to the
+//! next level unchanged (it is *not* duplicated, which would let two different
+//! leaf sets share a root).
+//!
+//! Proof steps are encoded as `"l:<hex>"` or `"r:<hex>"`, meaning the sibling
+//! sits to the left or right of the running hash. Promoted levels contribute
+//! no step, so a proof is self-describing and can be checked with only the
+//! leaf hash and the root.
+
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofStep {
+    pub side: Side,
+    pub sibling: Hash,
+}
+
+impl ProofStep {
+    pub fn encode(&self) -> String {
+        let tag = match self.side {
+            Side::Left => "l",
+            Side::Right => "r",
+        };
+        format!("{}:{}", tag, hex::encode(self.sibling))
+    }
+
+    pub fn decode(step: &str) -> Result<Self, String> {
+        let (tag, sibling) = step
+            .split_once(':')
+            .ok_or_else(|| format!("Malformed proof step: {}", step))?;
+        let side = match tag {
+            "l" => Side::Left,
+            "r" => Side::Right,
+            _ => return Err(format!("Unknown proof step side: {}", tag)),
+        };
+        Ok(ProofStep { side, sibling: decode_hash(sibling)? })
+    }
+}
+
+pub fn decode_hash(value: &str) -> Result<Hash, String> {
+    let bytes = hex::decode(value).map_err(|e| format!("Invalid hex hash: {}", e))?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("Hash must be 32 bytes: {}", value))
+}
+
+pub struct MerkleTree {
+    // levels[0] holds the leaves, the last level holds the root.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn build(leaves: Vec<Hash>) -> Self {
+        let mut levels = vec![leaves];
+        while levels.last().map(|l| l.len() > 1).unwrap_or(false) {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> Option<Hash> {
+        self.levels.last().and_then(|l| l.first().copied())
+    }
+
+    pub fn proof(&self, index: usize) -> Vec<ProofStep> {
+        let mut steps = Vec::new();
+        let mut index = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                let side = if sibling < index { Side::Left } else { Side::Right };
+                steps.push(ProofStep { side, sibling: level[sibling] });
+            }
+            index /= 2;
+        }
+        steps
+    }
+}
+
+pub fn compute_root(leaf: Hash, proof: &[ProofStep]) -> Hash {
+    proof.iter().fold(leaf, |acc, step| match step.side {
+        Side::Left => node_hash(&step.sibling, &acc),
+        Side::Right => node_hash(&acc, &step.sibling),
+    })
+}
+
+pub fn compute_root_encoded(leaf: &str, proof: &[String]) -> Result<Hash, String> {
+    let steps = proof
+        .iter()
+        .map(|s| ProofStep::decode(s))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(compute_root(decode_hash(leaf)?, &steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_leaf_proves_against_root() {
+        for size in 1..=9usize {
+            let leaves: Vec<Hash> = (0..size).map(|i| leaf_hash(&[i as u8])).collect();
+            let tree = MerkleTree::build(leaves.clone());
+            let root = tree.root().unwrap();
+            for (i, leaf) in leaves.iter().enumerate() {
+                let encoded: Vec<String> = tree.proof(i).iter().map(ProofStep::encode).collect();
+                let decoded: Vec<ProofStep> = encoded
+                    .iter()
+                    .map(|s| ProofStep::decode(s).unwrap())
+                    .collect();
+                assert_eq!(compute_root(*leaf, &decoded), root, "size {} leaf {}", size, i);
+            }
+        }
+    }
+
+    #[test]
+    fn odd_node_is_promoted_not_duplicated() {
+        let a = leaf_hash(b"a");
+        let b = leaf_hash(b"b");
+        let c = leaf_hash(b"c");
+        let three = MerkleTree::build(vec![a, b, c]).root().unwrap();
+        assert_eq!(three, node_hash(&node_hash(&a, &b), &c));
+        let four = MerkleTree::build(vec![a, b, c, c]).root().unwrap();
+        assert_ne!(three, four);
+    }
+}
@@ -0,0 +1,128 @@
+//! Receipt metadata and the leaf commitment covering it.
+//!
+//! Receipts commit to their full content, not just the data hash: every field
+//! is written length-prefixed into the leaf preimage, so changing the iQube ID,
+//! schema or a tag changes the leaf and breaks the inclusion proof.
+
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::data_hash::{HashAlgorithm, IssueError};
+use crate::Receipt;
+
+pub const LEAF_VERSION: u32 = 1;
+const LEAF_DOMAIN: &[u8] = b"iqube-receipt-v1";
+const MAX_TAGS: usize = 16;
+const MAX_FIELD_LEN: usize = 128;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReceiptMetadata {
+    pub iqube_id: Option<String>,
+    pub schema: Option<String>,
+    pub schema_version: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl ReceiptMetadata {
+    pub fn validate(&self) -> Result<(), IssueError> {
+        if self.tags.len() > MAX_TAGS {
+            return Err(IssueError::InvalidMetadata(format!("At most {} tags are allowed", MAX_TAGS)));
+        }
+        let fields = [&self.iqube_id, &self.schema, &self.schema_version];
+        for value in fields.into_iter().flatten().chain(&self.tags) {
+            if value.is_empty() || value.len() > MAX_FIELD_LEN || value.contains('\0') {
+                return Err(IssueError::InvalidMetadata(format!(
+                    "Metadata values must be 1-{} bytes without NUL: {:?}",
+                    MAX_FIELD_LEN, value
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn push_field(preimage: &mut Vec<u8>, value: &[u8]) {
+    preimage.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    preimage.extend_from_slice(value);
+}
+
+fn push_opt(preimage: &mut Vec<u8>, value: Option<&[u8]>) {
+    match value {
+        Some(value) => {
+            preimage.push(1);
+            push_field(preimage, value);
+        }
+        None => preimage.push(0),
+    }
+}
+
+/// Offset of the hex `data_hash` within `leaf_preimage(receipt)`.
+pub fn data_hash_offset(receipt: &Receipt) -> usize {
+    LEAF_DOMAIN.len() + 4 + receipt.id.len() + 4
+}
+
+/// Bytes hashed into the Merkle leaf for `receipt`.
+pub fn leaf_preimage(receipt: &Receipt) -> Vec<u8> {
+    let algorithm: &[u8] = match receipt.hash_algorithm {
+        HashAlgorithm::Sha256 => b"sha256",
+        HashAlgorithm::Keccak256 => b"keccak256",
+    };
+    let metadata = receipt.metadata.clone().unwrap_or_default();
+
+    let mut preimage = LEAF_DOMAIN.to_vec();
+    push_field(&mut preimage, receipt.id.as_bytes());
+    push_field(&mut preimage, receipt.data_hash.as_bytes());
+    push_field(&mut preimage, algorithm);
+    push_field(&mut preimage, &receipt.timestamp.to_be_bytes());
+    push_field(&mut preimage, receipt.issuer.as_slice());
+    push_opt(&mut preimage, metadata.iqube_id.as_deref().map(str::as_bytes));
+    push_opt(&mut preimage, metadata.schema.as_deref().map(str::as_bytes));
+    push_opt(&mut preimage, metadata.schema_version.as_deref().map(str::as_bytes));
+    preimage.extend_from_slice(&(metadata.tags.len() as u32).to_be_bytes());
+    for tag in &metadata.tags {
+        push_field(&mut preimage, tag.as_bytes());
+    }
+    preimage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(metadata: ReceiptMetadata) -> Receipt {
+        Receipt {
+            id: "receipt_0_00".to_string(),
+            data_hash: "ab".repeat(32),
+            hash_algorithm: HashAlgorithm::Sha256,
+            timestamp: 1,
+            merkle_proof: vec![],
+            issuer: candid::Principal::anonymous(),
+            sequence: 0,
+            metadata: Some(metadata),
+            leaf_version: LEAF_VERSION,
+        }
+    }
+
+    #[test]
+    fn leaf_commits_to_metadata() {
+        let tagged = ReceiptMetadata { iqube_id: Some("iq-1".to_string()), tags: vec!["a".to_string()], ..Default::default() };
+        let base = leaf_preimage(&receipt(tagged.clone()));
+        let retagged = ReceiptMetadata { tags: vec!["b".to_string()], ..tagged.clone() };
+        assert_ne!(base, leaf_preimage(&receipt(retagged)));
+        // Moving a value between fields must not produce the same preimage
+        let moved = ReceiptMetadata { iqube_id: None, schema: Some("iq-1".to_string()), ..tagged.clone() };
+        assert_ne!(base, leaf_preimage(&receipt(moved)));
+
+        let preimage = leaf_preimage(&receipt(tagged.clone()));
+        let offset = data_hash_offset(&receipt(tagged));
+        assert_eq!(&preimage[offset..offset + 64], "ab".repeat(32).as_bytes());
+    }
+
+    #[test]
+    fn oversized_metadata_is_rejected() {
+        let tags = ReceiptMetadata { tags: vec!["t".to_string(); MAX_TAGS + 1], ..Default::default() };
+        assert!(tags.validate().is_err());
+        let empty = ReceiptMetadata { schema: Some(String::new()), ..Default::default() };
+        assert!(empty.validate().is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Merkle Mountain Range hashing and proof checks.
+//!
+//! Batch `n` is leaf `n`. Nodes are addressed by `(height, index)`: the node at
+//! height `h`, index `i` covers leaves `[i << h, (i + 1) << h)` and is
+//! `node_hash(node(h-1, 2i), node(h-1, 2i+1))`. A range of `size` leaves has one
+//! perfect tree ("peak") per set bit of `size`, largest first. Its root bags the
+//! peaks right to left and commits to the size:
+//!
+//! ```text
+//! root = sha256(0x02 || size_be || node_hash(p0, node_hash(p1, ... pk)))
+//! ```
+//!
+//! Consistency between two sizes is proven by showing each old peak is a
+//! subtree of a new peak. Proof directions are derived from the node
+//! positions, never taken from the proof, so history cannot be reordered.
+
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::merkle::{self, Hash};
+
+const ROOT_PREFIX: u8 = 0x02;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MmrInclusionProof {
+    pub leaf_index: u64,
+    pub mmr_size: u64,
+    // Siblings from the leaf up to its peak
+    pub siblings: Vec<String>,
+    pub peaks: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct MmrConsistencyProof {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub old_peaks: Vec<String>,
+    pub new_peaks: Vec<String>,
+    // For each old peak, the siblings leading up to the new peak containing it
+    pub paths: Vec<Vec<String>>,
+}
+
+/// `(height, index)` of each peak for `size` leaves, left to right.
+pub fn peak_positions(size: u64) -> Vec<(u32, u64)> {
+    let mut peaks = Vec::new();
+    let mut covered = 0u64;
+    for height in (0..64u32).rev() {
+        if size & (1 << height) != 0 {
+            peaks.push((height, covered >> height));
+            covered += 1 << height;
+        }
+    }
+    peaks
+}
+
+// Folds peaks right to left; `None` for no peaks
+pub fn bag_peaks(peaks: &[Hash]) -> Option<Hash> {
+    peaks.iter().rev().copied().reduce(|acc, peak| merkle::node_hash(&peak, &acc))
+}
+
+// Bytes hashed in front of the bagged peaks
+pub fn root_prefix(size: u64) -> Vec<u8> {
+    [&[ROOT_PREFIX][..], &size.to_be_bytes()].concat()
+}
+
+pub fn bag(peaks: &[Hash], size: u64) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(root_prefix(size));
+    hasher.update(bag_peaks(peaks).unwrap_or([0; 32]));
+    hasher.finalize().into()
+}
+
+// Which peak of a `size`-leaf MMR holds `leaf_index`
+pub fn peak_slot(leaf_index: u64, size: u64) -> usize {
+    peak_positions(size)
+        .iter()
+        .position(|(h, i)| leaf_index >> h == *i)
+        .expect("every leaf is under a peak")
+}
+
+fn climb(mut hash: Hash, mut index: u64, siblings: &[String]) -> Result<(Hash, u64), String> {
+    for sibling in siblings {
+        let sibling = merkle::decode_hash(sibling)?;
+        hash = if index & 1 == 1 { merkle::node_hash(&sibling, &hash) } else { merkle::node_hash(&hash, &sibling) };
+        index >>= 1;
+    }
+    Ok((hash, index))
+}
+
+fn decode_all(hashes: &[String]) -> Result<Vec<Hash>, String> {
+    hashes.iter().map(|h| merkle::decode_hash(h)).collect()
+}
+
+/// The MMR root committed by `proof`, given the batch root it starts from.
+pub fn inclusion_root(batch_root: &Hash, proof: &MmrInclusionProof) -> Result<Hash, String> {
+    if proof.leaf_index >= proof.mmr_size {
+        return Err("Leaf index is outside the MMR".to_string());
+    }
+    let positions = peak_positions(proof.mmr_size);
+    let peaks = decode_all(&proof.peaks)?;
+    let slot = peak_slot(proof.leaf_index, proof.mmr_size);
+    if peaks.len() != positions.len() || proof.siblings.len() != positions[slot].0 as usize {
+        return Err("MMR proof has the wrong shape for its size".to_string());
+    }
+    let (peak, _) = climb(merkle::leaf_hash(batch_root), proof.leaf_index, &proof.siblings)?;
+    if peak != peaks[slot] {
+        return Err("MMR path does not lead to its peak".to_string());
+    }
+    Ok(bag(&peaks, proof.mmr_size))
+}
+
+/// Checks that the MMR with `new_root` extends the one with `old_root`.
+pub fn verify_consistency(proof: &MmrConsistencyProof, old_root: &Hash, new_root: &Hash) -> Result<(), String> {
+    if proof.old_size == 0 || proof.old_size > proof.new_size {
+        return Err("Consistency needs 0 < old_size <= new_size".to_string());
+    }
+    let old_positions = peak_positions(proof.old_size);
+    let new_positions = peak_positions(proof.new_size);
+    let old_peaks = decode_all(&proof.old_peaks)?;
+    let new_peaks = decode_all(&proof.new_peaks)?;
+    if old_peaks.len() != old_positions.len() || new_peaks.len() != new_positions.len() || proof.paths.len() != old_peaks.len() {
+        return Err("Consistency proof has the wrong shape for its sizes".to_string());
+    }
+    if bag(&old_peaks, proof.old_size) != *old_root {
+        return Err("Old peaks do not bag to the old root".to_string());
+    }
+    if bag(&new_peaks, proof.new_size) != *new_root {
+        return Err("New peaks do not bag to the new root".to_string());
+    }
+    for ((&(height, index), peak), siblings) in old_positions.iter().zip(&old_peaks).zip(&proof.paths) {
+        let (hash, top_index) = climb(*peak, index, siblings)?;
+        let top_height = height + siblings.len() as u32;
+        let slot = new_positions
+            .iter()
+            .position(|&(h, i)| h == top_height && i == top_index)
+            .ok_or_else(|| "Old peak does not climb to a new peak".to_string())?;
+        if hash != new_peaks[slot] {
+            return Err("Old peak is not contained in the new MMR".to_string());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,435 @@
+//! OpenTimestamps (`.ots`) proofs for receipts.
+//!
+//! Every hash between a receipt's data hash and the BTC block header is a
+//! SHA-256 over bytes we know, so the whole path can be written as OTS
+//! operations and checked with stock OTS tooling:
+//!
+//! ```text
+//! data hash --hexlify, prepend/append rest of leaf preimage, sha256--> leaf
+//!           --prepend 0x01 (+ sibling), append sibling, sha256-->      batch root
+//!           --same, then bag peaks and prepend 0x02 || size-->        MMR root
+//!           --prepend/append rest of anchor tx, sha256 x2-->           txid
+//!           --SPV path, sha256 x2 per level-->                         block Merkle root
+//!           BitcoinBlockHeaderAttestation(height)
+//! ```
+//!
+//! Accepted `.ots` files may fork, but may only use the operations listed in
+//! `Op`.
+
+use crate::bundle::parse_transaction;
+use crate::data_hash::HashAlgorithm;
+use crate::merkle::{self, Hash, ProofStep, Side};
+use crate::metadata;
+use crate::mmr::{self, MmrInclusionProof};
+use crate::{AnchorProof, Receipt};
+use sha2::{Digest, Sha256};
+
+const HEADER_MAGIC: &[u8] = b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94";
+const MAJOR_VERSION: u64 = 1;
+const MAX_ARG_LEN: usize = 4096;
+const MAX_DEPTH: usize = 256;
+
+const TAG_ATTESTATION: u8 = 0x00;
+const TAG_SHA256: u8 = 0x08;
+const TAG_KECCAK256: u8 = 0x67;
+const TAG_APPEND: u8 = 0xf0;
+const TAG_PREPEND: u8 = 0xf1;
+const TAG_REVERSE: u8 = 0xf2;
+const TAG_HEXLIFY: u8 = 0xf3;
+const TAG_FORK: u8 = 0xff;
+
+const BITCOIN_ATTESTATION: [u8; 8] = [0x05, 0x88, 0x96, 0x0d, 0x73, 0xd7, 0x19, 0x01];
+const PENDING_ATTESTATION: [u8; 8] = [0x83, 0xdf, 0xe3, 0x0d, 0x2e, 0xf9, 0x0c, 0x8e];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    Sha256,
+    Append(Vec<u8>),
+    Prepend(Vec<u8>),
+    Reverse,
+    Hexlify,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Attestation {
+    Bitcoin { height: u64 },
+    Pending { uri: String },
+    Unknown { tag: [u8; 8], payload: Vec<u8> },
+}
+
+/// A commitment tree: every op leads to a further timestamp, and any node may
+/// carry attestations for the message it represents.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timestamp {
+    pub attestations: Vec<Attestation>,
+    pub ops: Vec<(Op, Timestamp)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetachedTimestamp {
+    pub file_hash: HashAlgorithm,
+    pub digest: Hash,
+    pub timestamp: Timestamp,
+}
+
+impl Op {
+    fn apply(&self, msg: &[u8]) -> Vec<u8> {
+        match self {
+            Op::Sha256 => Sha256::digest(msg).to_vec(),
+            Op::Append(arg) => [msg, arg].concat(),
+            Op::Prepend(arg) => [arg, msg].concat(),
+            Op::Reverse => msg.iter().rev().copied().collect(),
+            Op::Hexlify => hex::encode(msg).into_bytes(),
+        }
+    }
+}
+
+impl Timestamp {
+    /// A single path of `ops` ending in `attestation`.
+    pub fn linear(ops: Vec<Op>, attestation: Attestation) -> Timestamp {
+        ops.into_iter().rev().fold(
+            Timestamp { attestations: vec![attestation], ops: vec![] },
+            |child, op| Timestamp { attestations: vec![], ops: vec![(op, child)] },
+        )
+    }
+
+    /// Every attestation in the tree with the message it attests to.
+    pub fn replay(&self, msg: &[u8]) -> Vec<(Attestation, Vec<u8>)> {
+        let mut found: Vec<(Attestation, Vec<u8>)> =
+            self.attestations.iter().map(|a| (a.clone(), msg.to_vec())).collect();
+        for (op, child) in &self.ops {
+            found.extend(child.replay(&op.apply(msg)));
+        }
+        found
+    }
+}
+
+impl DetachedTimestamp {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = HEADER_MAGIC.to_vec();
+        write_varuint(&mut out, MAJOR_VERSION);
+        out.push(match self.file_hash {
+            HashAlgorithm::Sha256 => TAG_SHA256,
+            HashAlgorithm::Keccak256 => TAG_KECCAK256,
+        });
+        out.extend_from_slice(&self.digest);
+        write_timestamp(&mut out, &self.timestamp);
+        out
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<DetachedTimestamp, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(HEADER_MAGIC.len())? != HEADER_MAGIC {
+            return Err("Not an OpenTimestamps proof".to_string());
+        }
+        let version = reader.varuint()?;
+        if version != MAJOR_VERSION {
+            return Err(format!("Unsupported OTS version {}", version));
+        }
+        let file_hash = match reader.byte()? {
+            TAG_SHA256 => HashAlgorithm::Sha256,
+            TAG_KECCAK256 => HashAlgorithm::Keccak256,
+            tag => return Err(format!("Unsupported OTS file hash 0x{:02x}", tag)),
+        };
+        let digest = reader.take(32)?.try_into().unwrap();
+        let timestamp = read_timestamp(&mut reader, 0)?;
+        if reader.pos != bytes.len() {
+            return Err("Trailing bytes after OTS proof".to_string());
+        }
+        Ok(DetachedTimestamp { file_hash, digest, timestamp })
+    }
+}
+
+fn write_varuint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_varbytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varuint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_timestamp(out: &mut Vec<u8>, timestamp: &Timestamp) {
+    let items = timestamp.attestations.len() + timestamp.ops.len();
+    let mut written = 0;
+    let mut separator = |out: &mut Vec<u8>| {
+        written += 1;
+        if written < items {
+            out.push(TAG_FORK);
+        }
+    };
+    for attestation in &timestamp.attestations {
+        separator(out);
+        out.push(TAG_ATTESTATION);
+        let (tag, payload) = match attestation {
+            Attestation::Bitcoin { height } => {
+                let mut payload = Vec::new();
+                write_varuint(&mut payload, *height);
+                (BITCOIN_ATTESTATION, payload)
+            }
+            Attestation::Pending { uri } => {
+                let mut payload = Vec::new();
+                write_varbytes(&mut payload, uri.as_bytes());
+                (PENDING_ATTESTATION, payload)
+            }
+            Attestation::Unknown { tag, payload } => (*tag, payload.clone()),
+        };
+        out.extend_from_slice(&tag);
+        write_varbytes(out, &payload);
+    }
+    for (op, child) in &timestamp.ops {
+        separator(out);
+        match op {
+            Op::Sha256 => out.push(TAG_SHA256),
+            Op::Append(arg) => {
+                out.push(TAG_APPEND);
+                write_varbytes(out, arg);
+            }
+            Op::Prepend(arg) => {
+                out.push(TAG_PREPEND);
+                write_varbytes(out, arg);
+            }
+            Op::Reverse => out.push(TAG_REVERSE),
+            Op::Hexlify => out.push(TAG_HEXLIFY),
+        }
+        write_timestamp(out, child);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| "Truncated OTS proof".to_string())?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varuint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("OTS varuint too long".to_string())
+    }
+
+    fn varbytes(&mut self, max_len: usize) -> Result<&'a [u8], String> {
+        let len = self.varuint()? as usize;
+        if len > max_len {
+            return Err(format!("OTS field of {} bytes exceeds {}", len, max_len));
+        }
+        self.take(len)
+    }
+}
+
+fn read_timestamp(reader: &mut Reader, depth: usize) -> Result<Timestamp, String> {
+    if depth > MAX_DEPTH {
+        return Err("OTS proof nests too deeply".to_string());
+    }
+    let mut timestamp = Timestamp::default();
+    loop {
+        let tag = reader.byte()?;
+        let (tag, more) = if tag == TAG_FORK { (reader.byte()?, true) } else { (tag, false) };
+        if tag == TAG_ATTESTATION {
+            let attestation_tag: [u8; 8] = reader.take(8)?.try_into().unwrap();
+            let payload = reader.varbytes(MAX_ARG_LEN)?;
+            let mut inner = Reader { bytes: payload, pos: 0 };
+            timestamp.attestations.push(match attestation_tag {
+                BITCOIN_ATTESTATION => Attestation::Bitcoin { height: inner.varuint()? },
+                PENDING_ATTESTATION => Attestation::Pending {
+                    uri: String::from_utf8(inner.varbytes(MAX_ARG_LEN)?.to_vec())
+                        .map_err(|_| "Pending attestation URI is not UTF-8".to_string())?,
+                },
+                tag => Attestation::Unknown { tag, payload: payload.to_vec() },
+            });
+        } else {
+            let op = match tag {
+                TAG_SHA256 => Op::Sha256,
+                TAG_APPEND => Op::Append(reader.varbytes(MAX_ARG_LEN)?.to_vec()),
+                TAG_PREPEND => Op::Prepend(reader.varbytes(MAX_ARG_LEN)?.to_vec()),
+                TAG_REVERSE => Op::Reverse,
+                TAG_HEXLIFY => Op::Hexlify,
+                tag => return Err(format!("Unsupported OTS operation 0x{:02x}", tag)),
+            };
+            timestamp.ops.push((op, read_timestamp(reader, depth + 1)?));
+        }
+        if !more {
+            return Ok(timestamp);
+        }
+    }
+}
+
+fn sha256d() -> [Op; 2] {
+    [Op::Sha256, Op::Sha256]
+}
+
+/// The OTS proof for `receipt`, whose batch was anchored by `proof`.
+pub fn receipt_timestamp(
+    receipt: &Receipt,
+    mmr_proof: &MmrInclusionProof,
+    proof: &AnchorProof,
+) -> Result<DetachedTimestamp, String> {
+    let file_hash = receipt.hash_algorithm;
+    let digest = merkle::decode_hash(&receipt.data_hash)?;
+
+    // Data hash -> receipt leaf
+    let preimage = metadata::leaf_preimage(receipt);
+    let offset = metadata::data_hash_offset(receipt);
+    let hex_len = receipt.data_hash.len();
+    let mut ops = vec![Op::Hexlify, Op::Prepend([&[0x00], &preimage[..offset]].concat())];
+    if offset + hex_len < preimage.len() {
+        ops.push(Op::Append(preimage[offset + hex_len..].to_vec()));
+    }
+    ops.push(Op::Sha256);
+
+    // Receipt leaf -> batch root
+    let mut root = crate::receipt_leaf(receipt);
+    for step in receipt.merkle_proof.iter().map(|s| ProofStep::decode(s)) {
+        let step = step?;
+        ops.extend(node_ops(&step));
+        root = merkle::compute_root(root, &[step]);
+    }
+
+    // Batch root -> MMR root
+    let expected = mmr::inclusion_root(&root, mmr_proof)?;
+    ops.push(Op::Prepend(vec![0x00]));
+    ops.push(Op::Sha256);
+    let mut index = mmr_proof.leaf_index;
+    for sibling in &mmr_proof.siblings {
+        let side = if index & 1 == 1 { Side::Left } else { Side::Right };
+        ops.extend(node_ops(&ProofStep { side, sibling: merkle::decode_hash(sibling)? }));
+        index >>= 1;
+    }
+    let peaks = mmr_proof.peaks.iter().map(|p| merkle::decode_hash(p)).collect::<Result<Vec<_>, _>>()?;
+    let slot = mmr::peak_slot(mmr_proof.leaf_index, mmr_proof.mmr_size);
+    if let Some(right) = mmr::bag_peaks(&peaks[slot + 1..]) {
+        ops.extend(node_ops(&ProofStep { side: Side::Right, sibling: right }));
+    }
+    for left in peaks[..slot].iter().rev() {
+        ops.extend(node_ops(&ProofStep { side: Side::Left, sibling: *left }));
+    }
+    ops.push(Op::Prepend(mmr::root_prefix(mmr_proof.mmr_size)));
+    ops.push(Op::Sha256);
+    root = expected;
+
+    // Batch root -> txid, in internal byte order
+    let raw_tx = hex::decode(&proof.raw_tx).map_err(|e| format!("Invalid raw transaction hex: {}", e))?;
+    let tx = parse_transaction(&raw_tx)?;
+    let commitment = [&[0x6a, 0x20], &root[..]].concat();
+    let position = tx
+        .stripped
+        .windows(commitment.len())
+        .position(|w| w == commitment)
+        .ok_or_else(|| "Anchor transaction has no OP_RETURN with the anchored root".to_string())?
+        + 2;
+    ops.push(Op::Prepend(tx.stripped[..position].to_vec()));
+    ops.push(Op::Append(tx.stripped[position + 32..].to_vec()));
+    ops.extend(sha256d());
+
+    // txid -> block Merkle root
+    let mut index = proof.tx_index;
+    for sibling in &proof.merkle_path {
+        let mut sibling = merkle::decode_hash(sibling)?;
+        sibling.reverse();
+        ops.push(if index & 1 == 1 { Op::Prepend(sibling.to_vec()) } else { Op::Append(sibling.to_vec()) });
+        ops.extend(sha256d());
+        index >>= 1;
+    }
+
+    let timestamp = Timestamp::linear(ops, Attestation::Bitcoin { height: proof.block_height });
+    Ok(DetachedTimestamp { file_hash, digest, timestamp })
+}
+
+// One Merkle level: sha256(0x01 || left || right)
+fn node_ops(step: &ProofStep) -> Vec<Op> {
+    match step.side {
+        Side::Left => vec![Op::Prepend([&[0x01], &step.sibling[..]].concat()), Op::Sha256],
+        Side::Right => vec![Op::Prepend(vec![0x01]), Op::Append(step.sibling.to_vec()), Op::Sha256],
+    }
+}
+
+/// Heights of the Bitcoin attestations in `ots` that commit `receipt` into the
+/// block described by `proof`. Attestations for other blocks cannot be checked
+/// here and are ignored.
+pub fn verify_receipt_timestamp(receipt: &Receipt, ots: &DetachedTimestamp, proof: &AnchorProof) -> Result<Vec<u64>, String> {
+    if receipt.hash_algorithm != ots.file_hash || hex::encode(ots.digest) != receipt.data_hash {
+        return Err("OTS proof is for a different data hash".to_string());
+    }
+    let header = hex::decode(&proof.block_header).map_err(|e| format!("Invalid block header hex: {}", e))?;
+    if header.len() != 80 {
+        return Err("Block header must be 80 bytes".to_string());
+    }
+    let merkle_root = &header[36..68];
+    Ok(ots
+        .timestamp
+        .replay(&ots.digest)
+        .into_iter()
+        .filter_map(|(attestation, msg)| match attestation {
+            Attestation::Bitcoin { height } if height == proof.block_height && msg == merkle_root => Some(height),
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::tests::sample_bundle;
+
+    #[test]
+    fn receipt_proof_replays_through_the_mmr_to_block_merkle_root() {
+        let bundle = sample_bundle();
+        let proof = bundle.anchor_proof.clone().unwrap();
+        let ots = receipt_timestamp(&bundle.receipt, &bundle.mmr_proof, &proof).unwrap();
+
+        let bytes = ots.serialize();
+        assert!(bytes.starts_with(HEADER_MAGIC));
+        let parsed = DetachedTimestamp::parse(&bytes).unwrap();
+        assert_eq!(parsed, ots);
+        assert_eq!(verify_receipt_timestamp(&bundle.receipt, &parsed, &proof), Ok(vec![proof.block_height]));
+
+        let mut wrong_block = proof.clone();
+        wrong_block.block_height += 1;
+        assert_eq!(verify_receipt_timestamp(&bundle.receipt, &parsed, &wrong_block), Ok(vec![]));
+    }
+
+    #[test]
+    fn forks_and_attestations_roundtrip() {
+        let timestamp = Timestamp {
+            attestations: vec![Attestation::Pending { uri: "https://alice.btc.calendar.opentimestamps.org".to_string() }],
+            ops: vec![
+                (Op::Append(vec![1, 2]), Timestamp::linear(vec![Op::Sha256], Attestation::Bitcoin { height: 300 })),
+                (Op::Reverse, Timestamp::linear(vec![Op::Hexlify], Attestation::Bitcoin { height: 1 })),
+            ],
+        };
+        let ots = DetachedTimestamp { file_hash: HashAlgorithm::Sha256, digest: [7; 32], timestamp };
+        let bytes = ots.serialize();
+        assert_eq!(DetachedTimestamp::parse(&bytes), Ok(ots.clone()));
+        // Bitcoin attestation at height 300: tag, then varbytes(varuint(300))
+        let attestation = [&[TAG_ATTESTATION][..], &BITCOIN_ATTESTATION, &[2, 0xac, 0x02]].concat();
+        assert!(bytes.windows(attestation.len()).any(|w| w == attestation));
+        assert_eq!(ots.timestamp.replay(&ots.digest).len(), 3);
+        assert!(DetachedTimestamp::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,3 @@
+use crate::{AnchorProof, Receipt};
+
+canister_storage::candid_storable!(Receipt, AnchorProof);
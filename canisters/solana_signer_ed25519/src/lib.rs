@@ -1,24 +1,44 @@
 use candid::{candid_method, CandidType};
-use ic_cdk::{query, update};
+use ic_cdk::{init, post_upgrade, query, update};
 use ic_cdk::api::management_canister::http_request::{
     http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
 };
 use ic_cdk::api::management_canister::ecdsa::{
     ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
 };
+use ic_stable_structures::StableCell;
 use serde::{Deserialize, Serialize};
 
+mod storage;
+
+use storage::Memory;
+
 // NOTE: Phase 1 scaffolding: minimal implementations returning placeholders.
 // Follow-up will implement threshold Ed25519 signing and real RPC HTTPS outcalls.
 
+const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
+
 thread_local! {
-    static RPC_URL: std::cell::RefCell<String> = std::cell::RefCell::new("https://api.devnet.solana.com".to_string());
+    static RPC_URL: std::cell::RefCell<StableCell<String, Memory>> = std::cell::RefCell::new(
+        StableCell::init(storage::memory(storage::RPC_URL_MEMORY), DEFAULT_RPC_URL.to_string())
+            .expect("failed to init RPC URL cell")
+    );
+}
+
+#[init]
+fn init() {
+    storage::init_schema();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    storage::migrate();
 }
 
 #[update(name = "get_latest_blockhash")]
 #[candid_method(update)]
 async fn get_latest_blockhash() -> String {
-    let url = RPC_URL.with(|r| r.borrow().clone());
+    let url = RPC_URL.with(|r| r.borrow().get().clone());
     let payload = serde_json::json!({
         "jsonrpc": "2.0",
         "id": 1,
@@ -40,7 +60,7 @@ async fn get_latest_blockhash() -> String {
 #[update(name = "send_raw_transaction")]
 #[candid_method(update)]
 async fn send_raw_transaction(signed_tx_base64: String) -> String {
-    let url = RPC_URL.with(|r| r.borrow().clone());
+    let url = RPC_URL.with(|r| r.borrow().get().clone());
     let payload = serde_json::json!({
         "jsonrpc": "2.0",
         "id": 1,
@@ -56,13 +76,13 @@ async fn send_raw_transaction(signed_tx_base64: String) -> String {
 #[update(name = "set_rpc_url")]
 #[candid_method(update)]
 fn set_rpc_url(url: String) {
-    RPC_URL.with(|r| *r.borrow_mut() = url);
+    RPC_URL.with(|r| r.borrow_mut().set(url).expect("failed to store RPC URL"));
 }
 
 #[query(name = "get_rpc_url")]
 #[candid_method(query)]
 fn get_rpc_url() -> String {
-    RPC_URL.with(|r| r.borrow().clone())
+    RPC_URL.with(|r| r.borrow().get().clone())
 }
 
 #[update(name = "get_solana_address")]
@@ -85,7 +105,7 @@ async fn get_solana_address() -> String {
 #[candid_method(update)]
 async fn get_balance(address: String) -> u64 {
     // Call Solana RPC getBalance
-    let url = RPC_URL.with(|r| r.borrow().clone());
+    let url = RPC_URL.with(|r| r.borrow().get().clone());
     let payload = serde_json::json!({
         "jsonrpc": "2.0",
         "id": 1,
@@ -102,7 +122,7 @@ async fn get_balance(address: String) -> u64 {
 #[candid_method(update)]
 async fn request_airdrop(address: String, lamports: u64) -> String {
     // Devnet only: requestAirdrop
-    let url = RPC_URL.with(|r| r.borrow().clone());
+    let url = RPC_URL.with(|r| r.borrow().get().clone());
     let payload = serde_json::json!({
         "jsonrpc": "2.0",
         "id": 1,
@@ -130,7 +150,7 @@ async fn build_and_sign_transfer_internal(to: String, lamports: u64) -> Result<S
     let from_address = solana_address_from_ed25519(&from_pubkey);
     
     // Get latest blockhash for transaction
-    let url = RPC_URL.with(|r| r.borrow().clone());
+    let url = RPC_URL.with(|r| r.borrow().get().clone());
     let blockhash_payload = serde_json::json!({
         "jsonrpc": "2.0",
         "id": 1,
@@ -248,7 +268,7 @@ struct TxStatus { slot: u64, signature: String }
 #[candid_method(update)]
 async fn get_transaction(signature: String) -> Option<TxStatus> {
     // Use getSignatureStatuses for lightweight status
-    let url = RPC_URL.with(|r| r.borrow().clone());
+    let url = RPC_URL.with(|r| r.borrow().get().clone());
     let payload = serde_json::json!({
         "jsonrpc": "2.0",
         "id": 1,
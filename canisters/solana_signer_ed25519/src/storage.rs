@@ -0,0 +1,28 @@
+//! Stable-memory layout for solana_signer_ed25519.
+//!
+//! The configured RPC URL lives in stable memory so it survives `dfx deploy
+//! --mode upgrade`. The memory manager comes from `canister_storage`.
+
+use canister_storage::MemoryId;
+
+pub use canister_storage::{memory, Memory};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Memory IDs are part of the on-chain layout: never reuse or renumber them.
+// Memory 0 holds the schema version (`canister_storage::SCHEMA_MEMORY`).
+pub const RPC_URL_MEMORY: MemoryId = MemoryId::new(1);
+
+pub fn init_schema() {
+    canister_storage::set_schema_version(SCHEMA_VERSION);
+}
+
+pub fn migrate() {
+    let migrated = canister_storage::migrate(SCHEMA_VERSION, |from| match from {
+        // Heap-only state from before stable storage is gone after the
+        // upgrade, so there is nothing to carry over.
+        0 => {}
+        _ => unreachable!("no migration from schema v{}", from),
+    });
+    migrated.unwrap_or_else(|e| ic_cdk::trap(&e));
+}
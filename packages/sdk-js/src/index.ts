@@ -93,7 +93,7 @@ const crossChainServiceIDL = ({ IDL }: any) => {
     'submit_dvn_message': IDL.Func([IDL.Nat32, IDL.Nat32, IDL.Vec(IDL.Nat8), IDL.Text], [IDL.Text], []),
     'get_dvn_message': IDL.Func([IDL.Text], [IDL.Opt(DVNMessage)], ['query']),
     'get_message_attestations': IDL.Func([IDL.Text], [IDL.Vec(Attestation)], ['query']),
-    'submit_attestation': IDL.Func([IDL.Text, IDL.Text, IDL.Vec(IDL.Nat8)], [IDL.Variant({ Ok: IDL.Text, Err: IDL.Text })], []),
+    'submit_attestation': IDL.Func([IDL.Text, IDL.Vec(IDL.Nat8)], [IDL.Variant({ Ok: IDL.Text, Err: IDL.Text })], []),
     'monitor_evm_transaction': IDL.Func([IDL.Nat32, IDL.Text, IDL.Text], [IDL.Variant({ Ok: IDL.Text, Err: IDL.Text })], []),
     'verify_layerzero_message': IDL.Func([IDL.Nat32, IDL.Text, IDL.Text], [IDL.Variant({ Ok: IDL.Bool, Err: IDL.Text })], []),
   });
@@ -518,11 +518,11 @@ export async function submitCrossChainMessage(sourceChainId: number, destChainId
   return messageId;
 }
 
-export async function attestDVNMessage(messageId: string, validators: string[] = ['v1','v2']): Promise<void> {
-  for (const v of validators) {
-    const sig = toBytes(`sig:${messageId}:${v}`);
-    await callICPCanister('cross_chain_service', 'submit_attestation', [messageId, v, sig]);
-  }
+// Attests as the agent's identity, which must be a registered validator
+export async function attestDVNMessage(messageId: string): Promise<void> {
+  const sig = toBytes(`sig:${messageId}`);
+  const result = await callICPCanister('cross_chain_service', 'submit_attestation', [messageId, sig]);
+  if (result && 'Err' in result) throw new Error(result.Err);
 }
 
 export async function getCrossChainMessageStatus(messageId: string): Promise<{ attestations: number; ready: boolean }> {